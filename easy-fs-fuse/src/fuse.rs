@@ -0,0 +1,416 @@
+//! Mount an image on the host through FUSE, built with the `fuse` feature.
+
+use super::{host_clock, open_image, unix_time_ms};
+use clap::ArgMatches;
+use easy_fs::Inode;
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory,
+    ReplyEmpty, ReplyEntry, ReplyWrite, Request, TimeOrNow,
+};
+use libc::{
+    EEXIST, EINVAL, EISDIR, ENOENT, ENOTDIR, ENOTEMPTY, EOPNOTSUPP, EPERM, FALLOC_FL_KEEP_SIZE,
+    FALLOC_FL_PUNCH_HOLE,
+};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::Result;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const BLOCK_SZ: u32 = 512;
+/// The kernel may cache attributes and entries this long, nobody else
+/// changes the image while it is mounted.
+const TTL: Duration = Duration::from_secs(1);
+
+/// The FUSE inode number is the easy-fs inode id plus one, as the root is 1.
+fn to_ino(inode: &Inode) -> u64 {
+    inode.inode_id() as u64 + 1
+}
+
+fn to_system_time(ms: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms)
+}
+
+fn to_ms(time: TimeOrNow) -> u64 {
+    match time {
+        TimeOrNow::SpecificTime(time) => unix_time_ms(time),
+        TimeOrNow::Now => host_clock(),
+    }
+}
+
+fn attr(inode: &Inode) -> FileAttr {
+    let (atime, mtime, ctime) = inode.times();
+    FileAttr {
+        ino: to_ino(inode),
+        size: inode.size(),
+        blocks: inode.blocks(),
+        atime: to_system_time(atime),
+        mtime: to_system_time(mtime),
+        ctime: to_system_time(ctime),
+        crtime: to_system_time(ctime),
+        kind: if inode.is_dir() {
+            FileType::Directory
+        } else {
+            FileType::RegularFile
+        },
+        perm: inode.mode(),
+        nlink: inode.nlink(),
+        // easy-fs has no owners, everything belongs to whoever mounts it
+        uid: unsafe { libc::getuid() },
+        gid: unsafe { libc::getgid() },
+        rdev: 0,
+        blksize: BLOCK_SZ,
+        flags: 0,
+    }
+}
+
+/// The inodes the kernel has been told about by their FUSE inode number,
+/// which it looks up before using them.
+struct EasyFuse {
+    inodes: HashMap<u64, Arc<Inode>>,
+}
+
+impl EasyFuse {
+    fn new(root: Arc<Inode>) -> Self {
+        let mut inodes = HashMap::new();
+        inodes.insert(to_ino(&root), root);
+        Self { inodes }
+    }
+
+    fn get(&self, ino: u64) -> Option<Arc<Inode>> {
+        self.inodes.get(&ino).cloned()
+    }
+
+    /// Remember `inode` and return its attributes.
+    fn known(&mut self, inode: Arc<Inode>) -> FileAttr {
+        let attr = attr(&inode);
+        self.inodes.insert(attr.ino, inode);
+        attr
+    }
+}
+
+impl Filesystem for EasyFuse {
+    /// Files unlinked while they were known are reclaimed as they are
+    /// forgotten, before everything is written back.
+    fn destroy(&mut self) {
+        self.inodes.retain(|ino, _| *ino == 1);
+        if let Some(root) = self.get(1) {
+            root.sync();
+        }
+    }
+
+    /// The kernel forgets an inode at once, however many times it looked it up.
+    fn forget(&mut self, _req: &Request<'_>, ino: u64, _nlookup: u64) {
+        if ino != 1 {
+            self.inodes.remove(&ino);
+        }
+    }
+
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let (dir, name) = match (self.get(parent), name.to_str()) {
+            (Some(dir), Some(name)) => (dir, name),
+            _ => return reply.error(ENOENT),
+        };
+        match dir.find(name) {
+            Some(inode) => reply.entry(&TTL, &self.known(inode), 0),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.get(ino) {
+            Some(inode) => reply.attr(&TTL, &attr(&inode)),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let inode = match self.get(ino) {
+            Some(inode) => inode,
+            None => return reply.error(ENOENT),
+        };
+        if let Some(mode) = mode {
+            inode.set_mode(mode as u16);
+        }
+        if let Some(size) = size {
+            if !inode.set_size(size) {
+                return reply.error(EISDIR);
+            }
+        }
+        if atime.is_some() || mtime.is_some() {
+            let (old_atime, old_mtime, _) = inode.times();
+            inode.set_times(
+                atime.map_or(old_atime, to_ms),
+                mtime.map_or(old_mtime, to_ms),
+            );
+        }
+        reply.attr(&TTL, &attr(&inode));
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match self.get(ino) {
+            Some(inode) => {
+                let mut buf = vec![0u8; size as usize];
+                let len = inode.read_at(offset as usize, &mut buf);
+                reply.data(&buf[..len]);
+            }
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self.get(ino) {
+            Some(inode) => reply.written(inode.write_at(offset as usize, data) as u32),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn fsync(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        match self.get(ino) {
+            Some(inode) => {
+                inode.sync();
+                reply.ok();
+            }
+            None => reply.error(ENOENT),
+        }
+    }
+
+    /// Allocating grows the file, a hole may only be punched keeping the size.
+    fn fallocate(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        let inode = match self.get(ino) {
+            Some(inode) => inode,
+            None => return reply.error(ENOENT),
+        };
+        let (offset, length) = (offset as usize, length as usize);
+        let done = match mode {
+            0 => inode.allocate(offset, length),
+            mode if mode == FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE => {
+                inode.punch_hole(offset, length)
+            }
+            _ => return reply.error(EOPNOTSUPP),
+        };
+        if done {
+            reply.ok();
+        } else {
+            reply.error(EINVAL);
+        }
+    }
+
+    /// The offset of an entry is the slot following it, as for `Inode::read_dir`.
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let dir = match self.get(ino) {
+            Some(dir) if dir.is_dir() => dir,
+            Some(_) => return reply.error(ENOTDIR),
+            None => return reply.error(ENOENT),
+        };
+        let mut slot = offset as usize;
+        while let Some((next, name, inode)) = dir.read_dir(slot) {
+            let kind = if inode.is_dir() {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            };
+            if reply.add(to_ino(&inode), next as i64, kind, name) {
+                break;
+            }
+            slot = next;
+        }
+        reply.ok();
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let (dir, name) = match (self.get(parent), name.to_str()) {
+            (Some(dir), Some(name)) => (dir, name),
+            _ => return reply.error(ENOENT),
+        };
+        match dir.create(name) {
+            Some(inode) => {
+                inode.set_mode((mode & !umask) as u16);
+                reply.created(&TTL, &self.known(inode), 0, 0, 0);
+            }
+            None => reply.error(EEXIST),
+        }
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        reply: ReplyEntry,
+    ) {
+        let (dir, name) = match (self.get(parent), name.to_str()) {
+            (Some(dir), Some(name)) => (dir, name),
+            _ => return reply.error(ENOENT),
+        };
+        match dir.mkdir(name) {
+            Some(inode) => {
+                inode.set_mode((mode & !umask) as u16);
+                reply.entry(&TTL, &self.known(inode), 0);
+            }
+            None => reply.error(EEXIST),
+        }
+    }
+
+    fn link(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        let (inode, dir, name) = match (self.get(ino), self.get(newparent), newname.to_str()) {
+            (Some(inode), Some(dir), Some(name)) => (inode, dir, name),
+            _ => return reply.error(ENOENT),
+        };
+        if inode.is_dir() {
+            return reply.error(EPERM);
+        }
+        if dir.link(name, &inode) {
+            reply.entry(&TTL, &attr(&inode), 0);
+        } else {
+            reply.error(EEXIST);
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let (dir, name) = match (self.get(parent), name.to_str()) {
+            (Some(dir), Some(name)) => (dir, name),
+            _ => return reply.error(ENOENT),
+        };
+        match dir.find(name) {
+            Some(inode) if inode.is_dir() => reply.error(EISDIR),
+            Some(_) if dir.unlink(name) => reply.ok(),
+            _ => reply.error(ENOENT),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let (dir, name) = match (self.get(parent), name.to_str()) {
+            (Some(dir), Some(name)) => (dir, name),
+            _ => return reply.error(ENOENT),
+        };
+        match dir.find(name) {
+            Some(inode) if !inode.is_dir() => reply.error(ENOTDIR),
+            Some(_) if dir.rmdir(name) => reply.ok(),
+            Some(_) => reply.error(ENOTEMPTY),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        let (dir, name, new_dir, new_name) = match (
+            self.get(parent),
+            name.to_str(),
+            self.get(newparent),
+            newname.to_str(),
+        ) {
+            (Some(dir), Some(name), Some(new_dir), Some(new_name)) => {
+                (dir, name, new_dir, new_name)
+            }
+            _ => return reply.error(ENOENT),
+        };
+        if dir.rename(name, &new_dir, new_name) {
+            reply.ok();
+        } else {
+            reply.error(EINVAL);
+        }
+    }
+}
+
+/// Mount the image and serve it until it is unmounted, the image is written
+/// back then.
+pub fn easy_fs_mount(matches: &ArgMatches) -> Result<()> {
+    let root = open_image(matches.value_of("image").unwrap())?;
+    let options = vec![
+        MountOption::FSName(String::from("easy-fs")),
+        MountOption::DefaultPermissions,
+    ];
+    fuser::mount2(
+        EasyFuse::new(root),
+        matches.value_of("mountpoint").unwrap(),
+        &options,
+    )
+}
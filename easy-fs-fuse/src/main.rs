@@ -1,9 +1,13 @@
-use clap::{App, Arg};
-use easy_fs::{BlockDevice, EasyFileSystem};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use easy_fs::{BlockDevice, EasyFileSystem, Inode};
 use std::fs::{read_dir, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "fuse")]
+mod fuse;
 
 const BLOCK_SZ: usize = 512;
 
@@ -27,42 +31,281 @@ impl BlockDevice for BlockFile {
     fn handle_irq(&self) { unimplemented!(); }
 }
 
+fn cli() -> App<'static, 'static> {
+    let image = || Arg::with_name("image").required(true).help("Image file");
+    let path = |help| Arg::with_name("path").required(true).help(help);
+    let geometry = |command: App<'static, 'static>| {
+        command
+            .arg(
+                Arg::with_name("size")
+                    .long("size")
+                    .takes_value(true)
+                    .default_value("16")
+                    .help("Image size in MiB"),
+            )
+            .arg(
+                Arg::with_name("inodes")
+                    .long("inodes")
+                    .takes_value(true)
+                    .default_value("4095")
+                    .help("Number of files and directories besides the root"),
+            )
+            .arg(
+                Arg::with_name("dirent-size")
+                    .long("dirent-size")
+                    .takes_value(true)
+                    .default_value("64")
+                    .help("Bytes in a directory entry, a power of two from 32 to 512, names are 5 bytes shorter"),
+            )
+            .arg(
+                Arg::with_name("linear-dirs")
+                    .long("linear-dirs")
+                    .help("Search directories entry by entry rather than by the hash of names"),
+            )
+    };
+    let app = App::new("EasyFileSystem tool")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(geometry(
+            SubCommand::with_name("pack")
+                .about("Create an image with the executables in the root")
+                .arg(
+                    Arg::with_name("source")
+                        .short("s")
+                        .long("source")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Executable source dir(with backslash)"),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .short("t")
+                        .long("target")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Executable target dir(with backslash)"),
+                ),
+        ))
+        .subcommand(geometry(
+            SubCommand::with_name("mkfs")
+                .about("Create an empty image")
+                .arg(image()),
+        ))
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Print the SuperBlock of an image")
+                .arg(image()),
+        )
+        .subcommand(
+            SubCommand::with_name("ls")
+                .about("List a directory")
+                .arg(image())
+                .arg(Arg::with_name("path").default_value("/")),
+        )
+        .subcommand(
+            SubCommand::with_name("tree")
+                .about("List a directory and everything below it")
+                .arg(image())
+                .arg(Arg::with_name("path").default_value("/")),
+        )
+        .subcommand(
+            SubCommand::with_name("cat")
+                .about("Print a file")
+                .arg(image())
+                .arg(path("File to print")),
+        )
+        .subcommand(
+            SubCommand::with_name("put")
+                .about("Copy a host file into an image, replacing the file there")
+                .arg(image())
+                .arg(Arg::with_name("host").required(true).help("Host file"))
+                .arg(path("File in the image")),
+        )
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Copy a file out of an image")
+                .arg(image())
+                .arg(path("File in the image"))
+                .arg(Arg::with_name("host").required(true).help("Host file")),
+        )
+        .subcommand(
+            SubCommand::with_name("rm")
+                .about("Remove a file or an empty directory")
+                .arg(image())
+                .arg(path("File or directory to remove")),
+        )
+        .subcommand(
+            SubCommand::with_name("fsck")
+                .about("Check an image for inconsistencies")
+                .arg(image())
+                .arg(
+                    Arg::with_name("repair")
+                        .short("r")
+                        .long("repair")
+                        .help("Fix the inconsistencies found"),
+                ),
+        );
+    #[cfg(feature = "fuse")]
+    let app = app.subcommand(
+        SubCommand::with_name("mount")
+            .about("Mount an image through FUSE until it is unmounted")
+            .arg(image())
+            .arg(
+                Arg::with_name("mountpoint")
+                    .required(true)
+                    .help("Directory to mount on"),
+            ),
+    );
+    app
+}
+
 fn main() {
-    easy_fs_pack().expect("Error when packing easy-fs!");
+    let code = run(&cli().get_matches()).unwrap_or_else(|err| {
+        eprintln!("easy-fs-fuse: {}", err);
+        1
+    });
+    std::process::exit(code);
 }
 
-fn easy_fs_pack() -> std::io::Result<()> {
-    let matches = App::new("EasyFileSystem packer")
-        .arg(
-            Arg::with_name("source")
-                .short("s")
-                .long("source")
-                .takes_value(true)
-                .help("Executable source dir(with backslash)"),
-        )
-        .arg(
-            Arg::with_name("target")
-                .short("t")
-                .long("target")
-                .takes_value(true)
-                .help("Executable target dir(with backslash)"),
-        )
-        .get_matches();
-    let src_path = matches.value_of("source").unwrap();
-    let target_path = matches.value_of("target").unwrap();
-    println!("src_path = {}\ntarget_path = {}", src_path, target_path);
+/// Run a subcommand and return the exit code.
+fn run(matches: &ArgMatches) -> Result<i32> {
+    match matches.subcommand() {
+        ("fsck", Some(matches)) => return easy_fs_fsck(matches),
+        ("pack", Some(matches)) => easy_fs_pack(matches)?,
+        ("mkfs", Some(matches)) => easy_fs_mkfs(matches)?,
+        ("info", Some(matches)) => easy_fs_info(matches)?,
+        ("ls", Some(matches)) => easy_fs_ls(matches)?,
+        ("tree", Some(matches)) => easy_fs_tree(matches)?,
+        ("cat", Some(matches)) => easy_fs_cat(matches)?,
+        ("put", Some(matches)) => easy_fs_put(matches)?,
+        ("get", Some(matches)) => easy_fs_get(matches)?,
+        ("rm", Some(matches)) => easy_fs_rm(matches)?,
+        #[cfg(feature = "fuse")]
+        ("mount", Some(matches)) => fuse::easy_fs_mount(matches)?,
+        _ => unreachable!(),
+    }
+    Ok(0)
+}
+
+/// Milliseconds since the Unix epoch.
+fn unix_time_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+fn host_clock() -> u64 {
+    unix_time_ms(SystemTime::now())
+}
+
+fn invalid_input(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn open_block_file(image_path: &str) -> Result<Arc<BlockFile>> {
+    easy_fs::set_clock(host_clock);
+    let f = OpenOptions::new().read(true).write(true).open(image_path)?;
+    Ok(Arc::new(BlockFile(Mutex::new(f))))
+}
+
+/// Format a new image of `--size` MiB with room for `--inodes` inodes, and
+/// return its root.
+fn create_image(image_path: &str, matches: &ArgMatches) -> Result<Arc<Inode>> {
+    let size: u32 = matches
+        .value_of("size")
+        .unwrap()
+        .parse()
+        .ok()
+        .filter(|size| *size > 0)
+        .ok_or_else(|| invalid_input(String::from("bad size")))?;
+    let inodes: u32 = matches
+        .value_of("inodes")
+        .unwrap()
+        .parse()
+        .map_err(|_| invalid_input(String::from("bad number of inodes")))?;
+    let dirent_size: u32 = matches
+        .value_of("dirent-size")
+        .unwrap()
+        .parse()
+        .ok()
+        .filter(|size: &u32| size.is_power_of_two() && (32..=BLOCK_SZ as u32).contains(size))
+        .ok_or_else(|| invalid_input(String::from("bad size of directory entries")))?;
+    let hashed_dirs = !matches.is_present("linear-dirs");
+    if hashed_dirs && dirent_size as usize == BLOCK_SZ {
+        return Err(invalid_input(String::from(
+            "hashed directories need entries smaller than a block",
+        )));
+    }
+    // one bit for each inode and the root in the inode bitmap
+    let inode_bitmap_blocks = inodes / (BLOCK_SZ as u32 * 8) + 1;
+    let total_blocks = size * 2048;
+    easy_fs::set_clock(host_clock);
     let block_file = Arc::new(BlockFile(Mutex::new({
         let f = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(format!("{}{}", target_path, "fs.img"))?;
-        f.set_len(16 * 2048 * 512).unwrap();
+            .truncate(true)
+            .open(image_path)?;
+        f.set_len(total_blocks as u64 * BLOCK_SZ as u64)?;
         f
     })));
-    // 16MiB, at most 4095 files
-    let efs = EasyFileSystem::create(block_file, 16 * 2048, 1);
-    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    let efs = EasyFileSystem::create(
+        block_file,
+        total_blocks,
+        inode_bitmap_blocks,
+        dirent_size,
+        hashed_dirs,
+    );
+    Ok(EasyFileSystem::root_inode(&efs))
+}
+
+/// Open the filesystem of an existing image.
+fn open_efs(image_path: &str) -> Result<Arc<easy_fs::Mutex<EasyFileSystem>>> {
+    EasyFileSystem::open(open_block_file(image_path)?).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: not an image of this version of easy-fs", image_path),
+        )
+    })
+}
+
+/// Open an existing image and return its root.
+fn open_image(image_path: &str) -> Result<Arc<Inode>> {
+    Ok(EasyFileSystem::root_inode(&open_efs(image_path)?))
+}
+
+/// Find the inode at `path`, which is relative to the root whether it starts
+/// with a slash or not.
+fn lookup(root: &Inode, path: &str) -> Result<Arc<Inode>> {
+    path.split('/').filter(|name| !name.is_empty()).try_fold(
+        root.find(".").unwrap(),
+        |dir, name| {
+            dir.find(name)
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("{}: not found", path)))
+        },
+    )
+}
+
+/// Split `path` into the directory and the name in it.
+fn lookup_parent<'a>(root: &Inode, path: &'a str) -> Result<(Arc<Inode>, &'a str)> {
+    let path = path.trim_end_matches('/');
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name.is_empty() {
+        return Err(invalid_input(format!("{}: not a file", path)));
+    }
+    Ok((lookup(root, dir)?, name))
+}
+
+fn read_all(inode: &Inode) -> Vec<u8> {
+    let mut content = vec![0u8; inode.size() as usize];
+    let len = inode.read_at(0, &mut content);
+    content.truncate(len);
+    content
+}
+
+fn easy_fs_pack(matches: &ArgMatches) -> Result<()> {
+    let src_path = matches.value_of("source").unwrap();
+    let target_path = matches.value_of("target").unwrap();
+    println!("src_path = {}\ntarget_path = {}", src_path, target_path);
+    let root_inode = create_image(&format!("{}{}", target_path, "fs.img"), matches)?;
     let apps: Vec<_> = read_dir(src_path)
         .unwrap()
         .into_iter()
@@ -77,20 +320,188 @@ fn easy_fs_pack() -> std::io::Result<()> {
         let mut host_file = File::open(format!("{}{}", target_path, app)).unwrap();
         let mut all_data: Vec<u8> = Vec::new();
         host_file.read_to_end(&mut all_data).unwrap();
+        let mtime = unix_time_ms(host_file.metadata()?.modified()?);
         // create a file in easy-fs
-        let inode = root_inode.create(app.as_str()).unwrap();
+        let inode = root_inode.create(app.as_str()).ok_or_else(|| {
+            invalid_input(format!(
+                "{}: longer than {} bytes",
+                app,
+                root_inode.name_length_limit()
+            ))
+        })?;
         // write data to easy-fs
         inode.write_at(0, all_data.as_slice());
+        // keep the host modification time
+        inode.set_times(mtime, mtime);
     }
     // list apps
     for app in root_inode.ls() {
         println!("{}", app);
     }
+    // the block cache is write-back
+    root_inode.sync();
+    Ok(())
+}
+
+fn easy_fs_mkfs(matches: &ArgMatches) -> Result<()> {
+    create_image(matches.value_of("image").unwrap(), matches)?.sync();
+    Ok(())
+}
+
+fn easy_fs_info(matches: &ArgMatches) -> Result<()> {
+    let efs = open_efs(matches.value_of("image").unwrap())?;
+    let efs = efs.lock();
+    efs.read_super_block(|super_block| println!("{:#?}", super_block));
     Ok(())
 }
 
+/// One line for each entry in the form of `drwxr-xr-x` but with the
+/// permission in octal, followed by the link count and the size.
+fn easy_fs_ls(matches: &ArgMatches) -> Result<()> {
+    let root = open_image(matches.value_of("image").unwrap())?;
+    let path = matches.value_of("path").unwrap();
+    let dir = lookup(&root, path)?;
+    if !dir.is_dir() {
+        return Err(invalid_input(format!("{}: not a directory", path)));
+    }
+    let mut slot = 0;
+    while let Some((next, name, inode)) = dir.read_dir(slot) {
+        slot = next;
+        println!(
+            "{}{:03o} {:>3} {:>9} {}",
+            if inode.is_dir() { 'd' } else { '-' },
+            inode.mode(),
+            inode.nlink(),
+            inode.size(),
+            name
+        );
+    }
+    Ok(())
+}
+
+fn easy_fs_tree(matches: &ArgMatches) -> Result<()> {
+    fn walk(dir: &Inode, depth: usize) {
+        let mut slot = 0;
+        while let Some((next, name, inode)) = dir.read_dir(slot) {
+            slot = next;
+            if name == "." || name == ".." {
+                continue;
+            }
+            if inode.is_dir() {
+                println!("{}{}/", "    ".repeat(depth), name);
+                walk(&inode, depth + 1);
+            } else {
+                println!("{}{}", "    ".repeat(depth), name);
+            }
+        }
+    }
+    let root = open_image(matches.value_of("image").unwrap())?;
+    let path = matches.value_of("path").unwrap();
+    let dir = lookup(&root, path)?;
+    if !dir.is_dir() {
+        return Err(invalid_input(format!("{}: not a directory", path)));
+    }
+    println!("{}", path);
+    walk(&dir, 1);
+    Ok(())
+}
+
+fn easy_fs_cat(matches: &ArgMatches) -> Result<()> {
+    let root = open_image(matches.value_of("image").unwrap())?;
+    let path = matches.value_of("path").unwrap();
+    let file = lookup(&root, path)?;
+    if file.is_dir() {
+        return Err(invalid_input(format!("{}: is a directory", path)));
+    }
+    std::io::stdout().write_all(&read_all(&file))
+}
+
+fn easy_fs_put(matches: &ArgMatches) -> Result<()> {
+    let root = open_image(matches.value_of("image").unwrap())?;
+    let path = matches.value_of("path").unwrap();
+    let mut host_file = File::open(matches.value_of("host").unwrap())?;
+    let mut all_data: Vec<u8> = Vec::new();
+    host_file.read_to_end(&mut all_data)?;
+    let mtime = unix_time_ms(host_file.metadata()?.modified()?);
+    let (dir, name) = lookup_parent(&root, path)?;
+    let file = match dir.find(name) {
+        Some(file) if file.is_dir() => {
+            return Err(invalid_input(format!("{}: is a directory", path)));
+        }
+        Some(file) => {
+            file.clear();
+            file
+        }
+        None => dir
+            .create(name)
+            .ok_or_else(|| invalid_input(format!("{}: cannot be created", path)))?,
+    };
+    file.write_at(0, &all_data);
+    file.set_times(mtime, mtime);
+    root.sync();
+    Ok(())
+}
+
+fn easy_fs_get(matches: &ArgMatches) -> Result<()> {
+    let root = open_image(matches.value_of("image").unwrap())?;
+    let path = matches.value_of("path").unwrap();
+    let file = lookup(&root, path)?;
+    if file.is_dir() {
+        return Err(invalid_input(format!("{}: is a directory", path)));
+    }
+    File::create(matches.value_of("host").unwrap())?.write_all(&read_all(&file))
+}
+
+fn easy_fs_rm(matches: &ArgMatches) -> Result<()> {
+    let root = open_image(matches.value_of("image").unwrap())?;
+    let path = matches.value_of("path").unwrap();
+    let (dir, name) = lookup_parent(&root, path)?;
+    let inode = lookup(&dir, name)?;
+    let removed = if inode.is_dir() {
+        dir.rmdir(name)
+    } else {
+        dir.unlink(name)
+    };
+    if !removed {
+        return Err(invalid_input(format!("{}: cannot be removed", path)));
+    }
+    root.sync();
+    Ok(())
+}
+
+/// Print the inconsistencies of an image, and return the exit code: 0 if
+/// there is none, 1 if they have been repaired, 4 if they are left.
+fn easy_fs_fsck(matches: &ArgMatches) -> Result<i32> {
+    let repair = matches.is_present("repair");
+    let efs = open_efs(matches.value_of("image").unwrap())?;
+    let problems = efs.lock().fsck(repair);
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    Ok(match (problems.is_empty(), repair) {
+        (true, _) => 0,
+        (false, true) => {
+            println!("{} problems repaired", problems.len());
+            1
+        }
+        (false, false) => {
+            println!(
+                "{} problems found, run with --repair to fix them",
+                problems.len()
+            );
+            4
+        }
+    })
+}
+
+/// The block cache is global and keyed by block id only, so tests that
+/// format an image must not run concurrently.
+#[cfg(test)]
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+
 #[test]
 fn efs_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
     let block_file = Arc::new(BlockFile(Mutex::new({
         let f = OpenOptions::new()
             .read(true)
@@ -100,8 +511,8 @@ fn efs_test() -> std::io::Result<()> {
         f.set_len(8192 * 512).unwrap();
         f
     })));
-    EasyFileSystem::create(block_file.clone(), 4096, 1);
-    let efs = EasyFileSystem::open(block_file.clone());
+    EasyFileSystem::create(block_file.clone(), 4096, 1, 64, true);
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
     let root_inode = EasyFileSystem::root_inode(&efs);
     root_inode.create("filea");
     root_inode.create("fileb");
@@ -151,3 +562,966 @@ fn efs_test() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+fn test_block_file() -> std::io::Result<Arc<BlockFile>> {
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open("target/fs.img")?;
+    f.set_len(8192 * 512)?;
+    Ok(Arc::new(BlockFile(Mutex::new(f))))
+}
+
+#[test]
+fn efs_dir_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_block_file()?;
+    EasyFileSystem::create(block_file.clone(), 4096, 1, 64, true);
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert!(root_inode.is_dir());
+    assert!(root_inode.find(".").unwrap().is_dir());
+    assert!(root_inode.find("..").unwrap().is_dir());
+    let dira = root_inode.mkdir("dira").unwrap();
+    assert!(root_inode.mkdir("dira").is_none());
+    let dirb = dira.mkdir("dirb").unwrap();
+    let filea = dirb.create("filea").unwrap();
+    assert!(filea.is_file());
+    // "." of itself, ".." of the sub-directory and the entry in its parent
+    assert_eq!(root_inode.nlink(), 3);
+    assert_eq!(dira.nlink(), 3);
+    assert_eq!(dirb.nlink(), 2);
+    assert_eq!(dirb.size(), BLOCK_SZ as u64);
+    assert!(filea.mkdir("dirc").is_none());
+    assert!(filea.find("..").is_none());
+    // the same name can live in different directories
+    root_inode.create("filea").unwrap();
+    filea.write_at(0, b"nested");
+    assert_eq!(filea.size(), 6);
+    assert_eq!(filea.blocks(), 1);
+    let mut buffer = [0u8; 16];
+    let dirb_again = root_inode.find("dira").unwrap().find("dirb").unwrap();
+    let len = dirb_again.find("filea").unwrap().read_at(0, &mut buffer);
+    assert_eq!(&buffer[..len], b"nested");
+    // ".." leads back to the parent
+    let dira_again = dirb.find("..").unwrap();
+    assert_eq!(dira_again.ls(), vec![String::from("dirb")]);
+    let mut names = root_inode.ls();
+    names.sort();
+    assert_eq!(names, vec![String::from("dira"), String::from("filea")]);
+    // only empty directories can be removed
+    assert!(!dira.rmdir("dirb"));
+    assert!(!dira.rmdir("."));
+    assert!(!dirb.rmdir("filea"));
+    assert!(!dira.rmdir("nonexistent"));
+    dira.mkdir("dirb2").unwrap();
+    assert!(dira.rmdir("dirb2"));
+    assert!(dira.find("dirb2").is_none());
+    dira.mkdir("dirb3").unwrap();
+    let mut names = dira.ls();
+    names.sort();
+    assert_eq!(names, vec![String::from("dirb"), String::from("dirb3")]);
+    // read_dir walks every entry, in the order of their hash
+    let mut slot = 0;
+    let mut entries = Vec::new();
+    while let Some((next, name, inode)) = dira.read_dir(slot) {
+        entries.push((name, inode.is_dir()));
+        slot = next;
+    }
+    entries.sort();
+    let names = [".", "..", "dirb", "dirb3"];
+    assert_eq!(
+        entries,
+        names
+            .iter()
+            .map(|name| (String::from(*name), true))
+            .collect::<Vec<_>>()
+    );
+    assert!(dira.read_dir(slot).is_none());
+    assert!(filea.read_dir(0).is_none());
+    Ok(())
+}
+
+#[test]
+fn efs_link_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_block_file()?;
+    EasyFileSystem::create(block_file.clone(), 4096, 1, 64, true);
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let dira = root_inode.mkdir("dira").unwrap();
+    let filea = root_inode.create("filea").unwrap();
+    filea.write_at(0, b"linked");
+    assert_eq!(filea.nlink(), 1);
+    assert!(dira.link("fileb", &filea));
+    assert!(!dira.link("fileb", &filea));
+    assert_eq!(filea.nlink(), 2);
+    assert_eq!(dira.find("fileb").unwrap().inode_id(), filea.inode_id());
+    // hard links to directories are not allowed
+    assert!(!root_inode.link("dirb", &dira));
+    assert!(root_inode.unlink("filea"));
+    assert!(!root_inode.unlink("filea"));
+    assert_eq!(filea.nlink(), 1);
+    assert!(!root_inode.unlink("dira"));
+    let mut buffer = [0u8; 16];
+    let len = dira.find("fileb").unwrap().read_at(0, &mut buffer);
+    assert_eq!(&buffer[..len], b"linked");
+    assert!(dira.unlink("fileb"));
+    assert!(dira.ls().is_empty());
+    // the image would be full if unlinked files were not reclaimed
+    let data = [0xa5u8; 64 * BLOCK_SZ];
+    for _ in 0..200 {
+        let file = dira.create("big").unwrap();
+        assert_eq!(file.write_at(0, &data), data.len());
+        assert!(dira.unlink("big"));
+    }
+    Ok(())
+}
+
+/// One `Inode` per inode while it is in use, and unlinked files reclaimed
+/// once the last one using them is gone.
+#[test]
+fn efs_inode_cache_test() {
+    use easy_fs::Problem;
+    let _guard = TEST_LOCK.lock().unwrap();
+    let device = Arc::new(CrashDevice::new(4096));
+    device.power_cycle();
+    let efs = EasyFileSystem::create(device.clone(), 4096, 1, 64, true);
+    let root = EasyFileSystem::root_inode(&efs);
+    assert!(Arc::ptr_eq(&root, &EasyFileSystem::root_inode(&efs)));
+    let filea = root.create("filea").unwrap();
+    assert!(Arc::ptr_eq(&filea, &root.find("filea").unwrap()));
+    assert!(Arc::ptr_eq(&root, &root.find(".").unwrap()));
+    let inode_id = filea.inode_id();
+    drop(filea);
+    assert_eq!(root.find("filea").unwrap().inode_id(), inode_id);
+
+    // still readable and writable once unlinked
+    let filea = root.find("filea").unwrap();
+    assert_eq!(filea.write_at(0, &[0x5a; 4 * BLOCK_SZ]), 4 * BLOCK_SZ);
+    assert!(root.unlink("filea"));
+    assert!(root.find("filea").is_none());
+    assert_eq!(filea.nlink(), 0);
+    assert!(!root.link("filea", &filea));
+    assert_eq!(filea.write_at(4 * BLOCK_SZ, b"more"), 4);
+    let mut buffer = [0u8; 4];
+    assert_eq!(filea.read_at(4 * BLOCK_SZ, &mut buffer), 4);
+    assert_eq!(&buffer, b"more");
+    assert_eq!(filea.blocks(), 5);
+    // left behind as it is if the system stops now
+    efs.lock().sync();
+    let problems = efs.lock().fsck(false);
+    assert_eq!(problems[0], Problem::LeakedInode(inode_id));
+    assert_eq!(problems.len(), 1 + 5);
+    // a replaced file as well
+    let fileb = root.create("fileb").unwrap();
+    fileb.write_at(0, b"replaced");
+    root.create("filec").unwrap();
+    assert!(root.rename("filec", &root, "fileb"));
+    assert_eq!(fileb.nlink(), 0);
+    assert_eq!(fileb.size(), 8);
+    // reclaimed on sync once dropped
+    drop(filea);
+    drop(fileb);
+    root.sync();
+    assert_eq!(efs.lock().fsck(false), vec![]);
+    // the inode is reused
+    assert_eq!(root.create("filed").unwrap().inode_id(), inode_id);
+    // a removed directory in use takes no new entries
+    let dir = root.mkdir("dir").unwrap();
+    assert!(root.rmdir("dir"));
+    assert_eq!(dir.nlink(), 0);
+    assert!(dir.create("file").is_none());
+    assert!(!root.rename("filed", &dir, "filed"));
+    drop(dir);
+    root.sync();
+    assert_eq!(efs.lock().fsck(false), vec![]);
+    device.power_cycle();
+}
+
+#[test]
+fn efs_rename_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_block_file()?;
+    EasyFileSystem::create(block_file.clone(), 4096, 1, 64, true);
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let read_str = |inode: &easy_fs::Inode| {
+        let mut buffer = [0u8; 64];
+        let len = inode.read_at(0, &mut buffer);
+        String::from_utf8(buffer[..len].to_vec()).unwrap()
+    };
+    let dira = root_inode.mkdir("dira").unwrap();
+    let dirb = root_inode.mkdir("dirb").unwrap();
+    root_inode.create("tmp").unwrap().write_at(0, b"new");
+    dira.create("data").unwrap().write_at(0, b"old");
+    // rename inside a directory
+    assert!(root_inode.rename("tmp", &root_inode, "tmp2"));
+    assert!(root_inode.find("tmp").is_none());
+    // replace an existing file in another directory
+    assert!(root_inode.rename("tmp2", &dira, "data"));
+    assert!(root_inode.find("tmp2").is_none());
+    assert_eq!(read_str(&dira.find("data").unwrap()), "new");
+    assert_eq!(dira.ls(), vec![String::from("data")]);
+    // a file cannot replace a directory and vice versa
+    assert!(!dira.rename("data", &root_inode, "dirb"));
+    assert!(!root_inode.rename("dirb", &dira, "data"));
+    assert!(!root_inode.rename("nonexistent", &dira, "data"));
+    assert!(!root_inode.rename("..", &dira, "up"));
+    // move a directory, its ".." follows
+    assert!(root_inode.rename("dirb", &dira, "dirb"));
+    let dirb_moved = dira.find("dirb").unwrap();
+    assert_eq!(dirb_moved.find("..").unwrap().ls(), dira.ls());
+    // an entry cannot replace the directory holding it
+    assert!(!dira.rename("data", &root_inode, "dira"));
+    assert!(!dira.rename("dirb", &root_inode, "dira"));
+    assert_eq!(read_str(&dira.find("data").unwrap()), "new");
+    // a directory cannot be moved into its own subtree
+    assert!(!root_inode.rename("dira", &dirb, "dira"));
+    assert!(!root_inode.rename("dira", &dira, "dira2"));
+    // an empty directory can be replaced by another directory
+    let dirc = root_inode.mkdir("dirc").unwrap();
+    dirc.create("file").unwrap();
+    assert!(!dira.rename("dirb", &root_inode, "dirc"));
+    assert!(dirc.unlink("file"));
+    assert!(dira.rename("dirb", &root_inode, "dirc"));
+    let mut names = root_inode.ls();
+    names.sort();
+    assert_eq!(names, vec![String::from("dira"), String::from("dirc")]);
+    Ok(())
+}
+
+#[test]
+fn efs_time_test() -> std::io::Result<()> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static TICKS: AtomicU64 = AtomicU64::new(1);
+    fn tick() -> u64 {
+        TICKS.fetch_add(1, Ordering::SeqCst)
+    }
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_block_file()?;
+    easy_fs::set_clock(tick);
+    EasyFileSystem::create(block_file.clone(), 4096, 1, 64, true);
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root_inode.mode(), 0o755);
+    let filea = root_inode.create("filea").unwrap();
+    assert_eq!(filea.mode(), 0o644);
+    let (atime, mtime, ctime) = filea.times();
+    assert!(atime > 0 && atime == mtime && mtime == ctime);
+    // the directory is modified by the new entry
+    let (_, dir_mtime, _) = root_inode.times();
+    assert!(dir_mtime >= ctime);
+    filea.write_at(0, b"time");
+    let (atime2, mtime2, ctime2) = filea.times();
+    assert_eq!(atime2, atime);
+    assert!(mtime2 > mtime && ctime2 == mtime2);
+    let mut buffer = [0u8; 4];
+    filea.read_at(0, &mut buffer);
+    let (atime3, mtime3, _) = filea.times();
+    assert!(atime3 > mtime2);
+    assert_eq!(mtime3, mtime2);
+    // only the first read after a change stores the time of access
+    filea.read_at(0, &mut buffer);
+    assert_eq!(filea.times().0, atime3);
+    // chmod and utime only change the inode
+    filea.set_mode(0o600);
+    assert_eq!(filea.mode(), 0o600);
+    filea.set_times(7, 8);
+    let (atime4, mtime4, ctime4) = filea.times();
+    assert_eq!((atime4, mtime4), (7, 8));
+    assert!(ctime4 > atime3);
+    assert!(root_inode.link("fileb", &filea));
+    assert!(filea.times().2 > ctime4);
+    easy_fs::set_clock(host_clock);
+    Ok(())
+}
+
+#[test]
+fn efs_cache_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_block_file()?;
+    EasyFileSystem::create(block_file.clone(), 4096, 1, 64, true);
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    // far fewer cached blocks than the file spans, so dirty ones get evicted
+    easy_fs::set_block_cache_capacity(4);
+    let mut data: Vec<u8> = (0..300 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    // the last block is unlike any other on the image
+    let last_block = data.len() - BLOCK_SZ;
+    data[last_block..].fill(0xa5);
+    let filea = root_inode.create("filea").unwrap();
+    assert_eq!(filea.write_at(0, &data), data.len());
+    let image_holds = |block: &[u8]| -> std::io::Result<bool> {
+        let mut image = Vec::new();
+        let mut file = block_file.0.lock().unwrap();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut image)?;
+        Ok(image.chunks(BLOCK_SZ).any(|chunk| chunk == block))
+    };
+    // the last written block is still cached until sync
+    assert!(!image_holds(&data[last_block..])?);
+    easy_fs::block_cache_sync_all();
+    assert!(image_holds(&data[last_block..])?);
+    let mut buffer = vec![0u8; data.len()];
+    assert_eq!(filea.read_at(0, &mut buffer), data.len());
+    assert_eq!(buffer, data);
+    easy_fs::set_block_cache_capacity(16);
+    Ok(())
+}
+
+/// An in-memory disk on which the writes after the first `limit` ones are lost
+/// when it is power cycled, as if the power was cut there.
+#[cfg(test)]
+struct CrashDevice {
+    disk: Mutex<Vec<u8>>,
+    /// blocks written after the crash, which can be read back until power off
+    volatile: Mutex<std::collections::HashMap<usize, Vec<u8>>>,
+    writes: std::sync::atomic::AtomicUsize,
+    limit: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(test)]
+impl CrashDevice {
+    fn new(blocks: usize) -> Self {
+        Self {
+            disk: Mutex::new(vec![0u8; blocks * BLOCK_SZ]),
+            volatile: Mutex::new(std::collections::HashMap::new()),
+            writes: std::sync::atomic::AtomicUsize::new(0),
+            limit: std::sync::atomic::AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    fn writes(&self) -> usize {
+        self.writes.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Lose the writes following the next `count` ones.
+    fn crash_after(&self, count: usize) {
+        self.limit
+            .store(self.writes() + count, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Drop what the cache and the device hold in memory and start over.
+    fn power_cycle(&self) {
+        easy_fs::block_cache_discard_all();
+        self.volatile.lock().unwrap().clear();
+        self.limit
+            .store(usize::MAX, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl BlockDevice for CrashDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        if let Some(data) = self.volatile.lock().unwrap().get(&block_id) {
+            buf.copy_from_slice(data);
+            return;
+        }
+        let disk = self.disk.lock().unwrap();
+        buf.copy_from_slice(&disk[block_id * BLOCK_SZ..(block_id + 1) * BLOCK_SZ]);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        use std::sync::atomic::Ordering;
+        if self.writes.fetch_add(1, Ordering::SeqCst) < self.limit.load(Ordering::SeqCst) {
+            let mut disk = self.disk.lock().unwrap();
+            disk[block_id * BLOCK_SZ..(block_id + 1) * BLOCK_SZ].copy_from_slice(buf);
+        } else {
+            self.volatile.lock().unwrap().insert(block_id, buf.to_vec());
+        }
+    }
+
+    fn handle_irq(&self) {
+        unimplemented!();
+    }
+}
+
+/// Every file and directory with its link count and content, in the order of paths.
+#[cfg(test)]
+type Snapshot = Vec<(String, u32, Vec<u8>)>;
+
+#[cfg(test)]
+fn snapshot(root: &easy_fs::Inode) -> Snapshot {
+    fn walk(inode: &easy_fs::Inode, path: &str, entries: &mut Snapshot) {
+        let mut slot = 0;
+        while let Some((next, name, child)) = inode.read_dir(slot) {
+            slot = next;
+            if name == "." || name == ".." {
+                continue;
+            }
+            let path = format!("{}/{}", path, name);
+            if child.is_dir() {
+                entries.push((path.clone() + "/", child.nlink(), Vec::new()));
+                walk(&child, path.as_str(), entries);
+            } else {
+                let mut content = vec![0u8; child.size() as usize];
+                child.read_at(0, &mut content);
+                entries.push((path, child.nlink(), content));
+            }
+        }
+    }
+    let mut entries = Vec::new();
+    walk(root, "", &mut entries);
+    entries.sort();
+    entries
+}
+
+/// Format `device`, then run updates covering the bitmaps, inodes and
+/// directory entries with a sync after each one. Return the snapshot
+/// before the updates and after each of them.
+#[cfg(test)]
+fn crash_workload(device: &Arc<CrashDevice>) -> Vec<Snapshot> {
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    let updates: Vec<fn(&easy_fs::Inode)> = vec![
+        |root| {
+            root.mkdir("dira").unwrap();
+        },
+        |root| {
+            let filea = root.find("dira").unwrap().create("filea").unwrap();
+            filea.write_at(0, &[b'a'; 3000]);
+        },
+        |root| {
+            root.create("fileb").unwrap().write_at(0, b"fileb");
+        },
+        |root| {
+            let filea = root.find("dira").unwrap().find("filea").unwrap();
+            assert!(root.link("linka", &filea));
+        },
+        |root| {
+            let dira = root.find("dira").unwrap();
+            assert!(root.rename("fileb", &dira, "fileb"));
+        },
+        |root| {
+            assert!(root.find("dira").unwrap().unlink("filea"));
+        },
+        |root| {
+            // appended, as file contents overwritten in place are not journaled;
+            // large enough to need the indirect1 block
+            let fileb = root.find("dira").unwrap().find("fileb").unwrap();
+            fileb.write_at(5, &[b'b'; 40 * BLOCK_SZ]);
+        },
+        |root| {
+            root.find("dira").unwrap().mkdir("dirb").unwrap();
+        },
+        |root| {
+            root.find("linka").unwrap().clear();
+        },
+        |root| {
+            assert!(root.find("dira").unwrap().rmdir("dirb"));
+        },
+        |root| {
+            assert!(root.rename("dira", root, "dirc"));
+        },
+        |root| {
+            assert!(root.unlink("linka"));
+        },
+    ];
+    let mut snapshots = vec![snapshot(&root)];
+    for update in updates {
+        update(&root);
+        efs.lock().sync();
+        snapshots.push(snapshot(&root));
+    }
+    snapshots
+}
+
+/// Cut the power at random points of the writes made by `crash_workload`,
+/// the filesystem must be opened in one of the states between the updates.
+#[test]
+fn efs_crash_test() {
+    use rand::Rng;
+    let _guard = TEST_LOCK.lock().unwrap();
+    let format = || {
+        let device = Arc::new(CrashDevice::new(4096));
+        // the cache may hold blocks of other tests
+        device.power_cycle();
+        EasyFileSystem::create(device.clone(), 4096, 1, 64, true);
+        device
+    };
+    let device = format();
+    let start = device.writes();
+    let snapshots = crash_workload(&device);
+    let total_writes = device.writes() - start;
+    device.power_cycle();
+    let mut rng = rand::thread_rng();
+    for _ in 0..64 {
+        let count = rng.gen_range(0..=total_writes);
+        let device = format();
+        device.crash_after(count);
+        crash_workload(&device);
+        device.power_cycle();
+        let efs = EasyFileSystem::open(device.clone()).unwrap();
+        let recovered = snapshot(&EasyFileSystem::root_inode(&efs));
+        assert!(
+            snapshots.contains(&recovered),
+            "inconsistent after a crash at write {} of {}",
+            count,
+            total_writes
+        );
+        assert_eq!(efs.lock().fsck(false), vec![]);
+        device.power_cycle();
+    }
+}
+
+/// Corrupt an image behind the back of easy-fs, then check and repair it.
+#[test]
+fn efs_fsck_test() {
+    use easy_fs::Problem;
+    use std::convert::TryInto;
+    let _guard = TEST_LOCK.lock().unwrap();
+    let device = Arc::new(CrashDevice::new(4096));
+    device.power_cycle();
+    let efs = EasyFileSystem::create(device.clone(), 4096, 1, 32, false);
+    let root = EasyFileSystem::root_inode(&efs);
+    // inode 1, 2 and 3
+    root.create("filea").unwrap().write_at(0, &[b'a'; 3000]);
+    root.create("fileb").unwrap().write_at(0, b"fileb");
+    root.create("filec").unwrap().write_at(0, b"filec");
+    efs.lock().sync();
+    assert_eq!(efs.lock().fsck(false), vec![]);
+    device.power_cycle();
+
+    let read_word = |block_id: u32, offset: usize| {
+        let mut block = [0u8; BLOCK_SZ];
+        device.read_block(block_id as usize, &mut block);
+        u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap())
+    };
+    let write_word = |block_id: u32, offset: usize, word: u32| {
+        let mut block = [0u8; BLOCK_SZ];
+        device.read_block(block_id as usize, &mut block);
+        block[offset..offset + 4].copy_from_slice(&word.to_le_bytes());
+        device.write_block(block_id as usize, &block);
+    };
+    // the areas as in the SuperBlock, and the fields as in DiskInode and DirEntry
+    let [inode_bitmap_blocks, inode_area_blocks, data_bitmap_blocks, journal_blocks] =
+        [8, 12, 16, 24].map(|offset| read_word(0, offset));
+    let inode_area = 1 + journal_blocks + inode_bitmap_blocks;
+    let data_bitmap = inode_area + inode_area_blocks;
+    let data_area = data_bitmap + data_bitmap_blocks;
+    let inode_pos = |inode_id: u32| (inode_area + inode_id / 4, (inode_id % 4) as usize * 128);
+    let (size, direct0, nlink, dirent_inode) = (0, 4, 88, 28);
+
+    let root_block = read_word(inode_pos(0).0, inode_pos(0).1 + direct0);
+    let filea_blocks: Vec<u32> = (0..6)
+        .map(|i| read_word(inode_pos(1).0, inode_pos(1).1 + direct0 + 4 * i))
+        .collect();
+    let fileb_block = read_word(inode_pos(2).0, inode_pos(2).1 + direct0);
+    let filec_block = read_word(inode_pos(3).0, inode_pos(3).1 + direct0);
+    // "filea" after "." and ".." refers to a free inode
+    write_word(root_block, 2 * 32 + dirent_inode, 100);
+    // fileb claims more than its block, the next one is in the journal, and more links
+    write_word(inode_pos(2).0, inode_pos(2).1 + size, 100 * BLOCK_SZ as u32);
+    write_word(inode_pos(2).0, inode_pos(2).1 + direct0 + 4, 1);
+    write_word(inode_pos(2).0, inode_pos(2).1 + nlink, 5);
+    // filec shares the block of fileb
+    write_word(inode_pos(3).0, inode_pos(3).1 + direct0, fileb_block);
+    // the block of the root is free in the bitmap
+    let bit = root_block - data_area;
+    let bits = read_word(data_bitmap, bit as usize / 32 * 4);
+    write_word(
+        data_bitmap,
+        bit as usize / 32 * 4,
+        bits & !(1 << (bit % 32)),
+    );
+
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    let mut expected = vec![
+        Problem::DanglingDirent {
+            dir_id: 0,
+            name: String::from("filea"),
+            inode_id: 100,
+        },
+        Problem::SizeMismatch {
+            inode_id: 2,
+            size: 100 * BLOCK_SZ as u64,
+            fixed_size: BLOCK_SZ as u64,
+        },
+        Problem::LeakedInode(1),
+        Problem::NlinkMismatch {
+            inode_id: 2,
+            nlink: 5,
+            links: 1,
+        },
+        Problem::DuplicateBlock {
+            block_id: fileb_block,
+            inode_id: 3,
+        },
+        Problem::UnmarkedBlock(root_block),
+    ];
+    expected.extend(filea_blocks.into_iter().map(Problem::LeakedBlock));
+    expected.push(Problem::LeakedBlock(filec_block));
+    let mut problems = efs.lock().fsck(true);
+    problems.sort_by_key(|problem| format!("{:?}", problem));
+    expected.sort_by_key(|problem| format!("{:?}", problem));
+    assert_eq!(problems, expected);
+    device.power_cycle();
+
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    assert_eq!(efs.lock().fsck(false), vec![]);
+    let root = EasyFileSystem::root_inode(&efs);
+    assert!(root.find("filea").is_none());
+    let fileb = root.find("fileb").unwrap();
+    assert_eq!((fileb.size(), fileb.nlink()), (BLOCK_SZ as u64, 1));
+    let mut buffer = [0u8; 5];
+    root.find("filec").unwrap().read_at(0, &mut buffer);
+    assert_eq!(&buffer, b"fileb");
+    device.power_cycle();
+}
+
+/// Names up to the limit of the directory entry size, and images written
+/// before the size was recorded.
+#[test]
+fn efs_long_name_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let block_file = test_block_file()?;
+    let efs = EasyFileSystem::create(block_file.clone(), 4096, 1, 64, true);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root_inode.name_length_limit(), 59);
+    let long_name = "l".repeat(59);
+    let too_long_name = "l".repeat(60);
+    let filea = root_inode.create(long_name.as_str()).unwrap();
+    assert!(root_inode.create(too_long_name.as_str()).is_none());
+    assert!(root_inode.mkdir(too_long_name.as_str()).is_none());
+    assert!(!root_inode.link(too_long_name.as_str(), &filea));
+    assert!(!root_inode.rename(&long_name, &root_inode, &too_long_name));
+    filea.write_at(0, b"long");
+    root_inode.sync();
+
+    let efs = EasyFileSystem::open(block_file.clone()).unwrap();
+    assert_eq!(efs.lock().dirent_size(), 64);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root_inode.ls(), vec![long_name.clone()]);
+    let mut buffer = [0u8; 4];
+    let filea = root_inode.find(long_name.as_str()).unwrap();
+    assert_eq!(filea.read_at(0, &mut buffer), 4);
+    assert_eq!(&buffer, b"long");
+    assert_eq!(efs.lock().fsck(false), vec![]);
+
+    // images made before the size was recorded have zero in its place
+    let device = Arc::new(CrashDevice::new(4096));
+    device.power_cycle();
+    let efs = EasyFileSystem::create(device.clone(), 4096, 1, 32, false);
+    EasyFileSystem::root_inode(&efs).create("filea").unwrap();
+    efs.lock().sync();
+    device.power_cycle();
+    let mut super_block = [0u8; BLOCK_SZ];
+    device.read_block(0, &mut super_block);
+    assert_eq!(super_block[28..32], 32u32.to_le_bytes());
+    super_block[28..32].copy_from_slice(&[0; 4]);
+    device.write_block(0, &super_block);
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    assert_eq!(efs.lock().dirent_size(), 32);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root_inode.name_length_limit(), 27);
+    assert_eq!(root_inode.ls(), vec![String::from("filea")]);
+    device.power_cycle();
+    Ok(())
+}
+
+/// Images of an older version, whose inodes are laid out differently, are not
+/// opened.
+#[test]
+fn efs_magic_test() {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let device = Arc::new(CrashDevice::new(4096));
+    device.power_cycle();
+    EasyFileSystem::create(device.clone(), 4096, 1, 32, false);
+    device.power_cycle();
+    assert!(EasyFileSystem::open(device.clone()).is_some());
+    for magic in [0x3b800001u32, 0x3b800002] {
+        device.power_cycle();
+        let mut super_block = [0u8; BLOCK_SZ];
+        device.read_block(0, &mut super_block);
+        super_block[0..4].copy_from_slice(&magic.to_ne_bytes());
+        device.write_block(0, &super_block);
+        assert!(EasyFileSystem::open(device.clone()).is_none());
+    }
+    device.power_cycle();
+}
+
+/// Many entries in a hashed directory, which grows a bucket at a time and
+/// displaces entries from full buckets.
+#[test]
+fn efs_hash_dir_test() {
+    use easy_fs::Problem;
+    use std::convert::TryInto;
+    let _guard = TEST_LOCK.lock().unwrap();
+    let device = Arc::new(CrashDevice::new(4096));
+    device.power_cycle();
+    let efs = EasyFileSystem::create(device.clone(), 4096, 1, 64, true);
+    let root = EasyFileSystem::root_inode(&efs);
+    let name = |i: usize| format!("file{}", i);
+    let count = 2000;
+    let ids: Vec<u32> = (0..count)
+        .map(|i| root.create(&name(i)).unwrap().inode_id())
+        .collect();
+    assert!(root.create(&name(0)).is_none());
+    for (i, inode_id) in ids.iter().enumerate() {
+        assert_eq!(root.find(&name(i)).unwrap().inode_id(), *inode_id);
+    }
+    assert!(root.find("file").is_none());
+    // 7 entries in a bucket besides the header
+    let buckets = root.size() as usize / BLOCK_SZ;
+    assert!(buckets > (count + 2) / 7 && buckets < (count + 2) / 7 * 2);
+    // every other one is removed, then created again
+    for i in (0..count).step_by(2) {
+        assert!(root.unlink(&name(i)));
+    }
+    for i in 0..count {
+        assert_eq!(root.find(&name(i)).is_some(), i % 2 == 1);
+    }
+    for i in (0..count).step_by(2) {
+        root.create(&name(i)).unwrap();
+    }
+    let mut names = root.ls();
+    names.sort();
+    let mut expected: Vec<String> = (0..count).map(name).collect();
+    expected.sort();
+    assert_eq!(names, expected);
+    efs.lock().sync();
+    assert_eq!(efs.lock().fsck(false), vec![]);
+    device.power_cycle();
+
+    // count one more entry displaced from the first bucket of the root
+    let word = |block: &[u8], offset: usize| {
+        u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap())
+    };
+    let mut block = [0u8; BLOCK_SZ];
+    device.read_block(0, &mut block);
+    // the inode area follows the journal and the inode bitmap
+    let inode_area = 1 + word(&block, 24) + word(&block, 8);
+    device.read_block(inode_area as usize, &mut block);
+    let root_block = word(&block, 4) as usize;
+    device.read_block(root_block, &mut block);
+    let displaced = word(&block, 60);
+    block[60..64].copy_from_slice(&(displaced + 1).to_le_bytes());
+    device.write_block(root_block, &block);
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    assert_eq!(efs.lock().fsck(true), vec![Problem::BadHashIndex(0)]);
+    assert_eq!(efs.lock().fsck(false), vec![]);
+    device.power_cycle();
+
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    for i in 0..count {
+        assert!(root.find(&name(i)).is_some());
+    }
+    device.power_cycle();
+}
+
+/// Run the subcommands one after another on an image, each opening it again.
+#[test]
+fn efs_cli_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let run_command = |args: &[&str]| {
+        // each command is a process of its own otherwise
+        easy_fs::block_cache_discard_all();
+        let mut argv = vec!["easy-fs-fuse"];
+        argv.extend_from_slice(args);
+        run(&cli().get_matches_from(argv))
+    };
+    let image = "target/cli.img";
+    let host_file = "target/cli.txt";
+    let content = "Hello, world!\n".repeat(100);
+    std::fs::write(host_file, &content)?;
+    run_command(&["mkfs", image, "--size", "2", "--inodes", "5000"])?;
+    assert_eq!(std::fs::metadata(image)?.len(), 2 * 1024 * 1024);
+    run_command(&["put", image, host_file, "/hello"])?;
+    // replaced rather than appended
+    run_command(&["put", image, host_file, "hello"])?;
+    assert!(run_command(&["put", image, host_file, "/nodir/hello"]).is_err());
+    std::fs::remove_file(host_file)?;
+    run_command(&["get", image, "/hello", host_file])?;
+    assert_eq!(std::fs::read_to_string(host_file)?, content);
+    run_command(&["ls", image, "/"])?;
+    run_command(&["tree", image])?;
+    run_command(&["info", image])?;
+    assert_eq!(run_command(&["fsck", image])?, 0);
+    run_command(&["rm", image, "/hello"])?;
+    assert!(run_command(&["cat", image, "/hello"]).is_err());
+    assert!(run_command(&["rm", image, "/"]).is_err());
+
+    easy_fs::block_cache_discard_all();
+    let root = open_image(image)?;
+    assert!(root.ls().is_empty());
+    let inode_bitmap_blocks = open_efs(image)?
+        .lock()
+        .read_super_block(|super_block| super_block.inode_bitmap_blocks);
+    assert_eq!(inode_bitmap_blocks, 2);
+    easy_fs::block_cache_discard_all();
+    Ok(())
+}
+
+/// A file beyond what the double indirect block reaches, and the limit of
+/// the triple indirect block.
+#[test]
+fn efs_large_file_test() {
+    use easy_fs::MAX_FILE_SIZE;
+    let _guard = TEST_LOCK.lock().unwrap();
+    let device = Arc::new(CrashDevice::new(24576));
+    device.power_cycle();
+    let efs = EasyFileSystem::create(device.clone(), 24576, 1, 64, true);
+    let root = EasyFileSystem::root_inode(&efs);
+    let file = root.create("large").unwrap();
+    let chunk_len = 64 * 1024;
+    let chunk = |i: usize| vec![i as u8; chunk_len];
+    // 9MiB, 18432 data blocks while the double indirect block ends at 16531
+    let chunks = 144;
+    for i in 0..chunks {
+        assert_eq!(file.write_at(i * chunk_len, &chunk(i)), chunk_len);
+    }
+    assert_eq!(file.size(), (chunks * chunk_len) as u64);
+    // indirect1, indirect2 with 128 below it, indirect3 with 1 and 15 below it
+    assert_eq!(file.blocks(), 18432 + 1 + 129 + 17);
+    efs.lock().sync();
+    device.power_cycle();
+
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    let file = root.find("large").unwrap();
+    let mut buffer = vec![0u8; chunk_len];
+    for i in 0..chunks {
+        assert_eq!(file.read_at(i * chunk_len, &mut buffer), chunk_len);
+        assert_eq!(buffer, chunk(i));
+    }
+    assert_eq!(efs.lock().fsck(false), vec![]);
+    // nothing is written beyond the largest size, and a write is cut there
+    assert_eq!(file.write_at(MAX_FILE_SIZE as usize, b"x"), 0);
+    assert_eq!(file.size(), (chunks * chunk_len) as u64);
+    file.clear();
+    assert_eq!(file.blocks(), 0);
+    efs.lock().sync();
+    assert_eq!(efs.lock().fsck(false), vec![]);
+    device.power_cycle();
+}
+
+/// Holes left by writing beyond the end, punched, and allocated again.
+#[test]
+fn efs_sparse_test() {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let device = Arc::new(CrashDevice::new(4096));
+    device.power_cycle();
+    let efs = EasyFileSystem::create(device.clone(), 4096, 1, 64, true);
+    let root = EasyFileSystem::root_inode(&efs);
+    let file = root.create("sparse").unwrap();
+    // 10MiB is below the triple indirect block
+    let end = 10 * 1024 * 1024;
+    assert_eq!(file.write_at(end, b"end"), 3);
+    assert_eq!(file.size(), end as u64 + 3);
+    assert_eq!(file.blocks(), 4);
+    let mut buffer = vec![1u8; 4096];
+    assert_eq!(file.read_at(end - 4093, &mut buffer), 4096);
+    assert!(buffer[..4093].iter().all(|byte| *byte == 0));
+    assert_eq!(&buffer[4093..], b"end");
+
+    assert_eq!(file.write_at(0, &[b'a'; 3000]), 3000);
+    assert_eq!(file.blocks(), 10);
+    // blocks 1 to 4 are freed, what is left of 0 and 5 is zeroed
+    assert!(file.punch_hole(100, 2900));
+    assert_eq!(file.blocks(), 6);
+    let mut buffer = [1u8; 3000];
+    file.read_at(0, &mut buffer);
+    assert!(buffer[..100].iter().all(|byte| *byte == b'a'));
+    assert!(buffer[100..].iter().all(|byte| *byte == 0));
+    // the last block takes the index blocks above it along
+    assert!(file.punch_hole(end, 3));
+    assert_eq!(file.size(), end as u64 + 3);
+    assert_eq!(file.blocks(), 2);
+    assert!(file.allocate(0, 8 * BLOCK_SZ));
+    assert_eq!(file.blocks(), 8);
+    assert_eq!(file.size(), end as u64 + 3);
+    assert!(!root.allocate(0, BLOCK_SZ));
+    assert!(!root.punch_hole(0, BLOCK_SZ));
+    efs.lock().sync();
+    assert_eq!(efs.lock().fsck(false), vec![]);
+    device.power_cycle();
+
+    let efs = EasyFileSystem::open(device.clone()).unwrap();
+    let root = EasyFileSystem::root_inode(&efs);
+    let file = root.find("sparse").unwrap();
+    assert_eq!(file.blocks(), 8);
+    let mut buffer = [1u8; 3];
+    assert_eq!(file.read_at(end, &mut buffer), 3);
+    assert_eq!(buffer, [0; 3]);
+    // growing by an allocation
+    assert!(file.allocate(end + 3, BLOCK_SZ));
+    assert_eq!(file.size(), (end + 3 + BLOCK_SZ) as u64);
+    assert_eq!(file.blocks(), 8 + 2 + 3);
+    // cutting the file short frees what is beyond, growing leaves a hole
+    assert!(file.set_size(100));
+    assert_eq!(file.blocks(), 1);
+    assert!(file.set_size(2 * BLOCK_SZ as u64));
+    assert_eq!(file.blocks(), 1);
+    let mut buffer = vec![1u8; 2 * BLOCK_SZ];
+    assert_eq!(file.read_at(0, &mut buffer), 2 * BLOCK_SZ);
+    assert!(buffer[..100].iter().all(|byte| *byte == b'a'));
+    assert!(buffer[100..].iter().all(|byte| *byte == 0));
+    assert!(!root.set_size(0));
+    file.clear();
+    efs.lock().sync();
+    assert_eq!(efs.lock().fsck(false), vec![]);
+    device.power_cycle();
+}
+
+/// Threads creating, writing and removing files in their own directories
+/// while another one lists the root, through few cached blocks.
+#[test]
+fn efs_concurrent_test() {
+    let _guard = TEST_LOCK.lock().unwrap();
+    let device = Arc::new(CrashDevice::new(8192));
+    device.power_cycle();
+    let efs = EasyFileSystem::create(device.clone(), 8192, 1, 64, true);
+    let root = EasyFileSystem::root_inode(&efs);
+    easy_fs::set_block_cache_capacity(8);
+    let content = |t: usize, i: usize| vec![(t * 16 + i) as u8; 3 * BLOCK_SZ + t * 100 + i];
+    let workers: Vec<_> = (0..4)
+        .map(|t| {
+            let root = root.clone();
+            std::thread::spawn(move || {
+                let dir = root.mkdir(&format!("dir{}", t)).unwrap();
+                for i in 0..16 {
+                    let file = dir.create(&format!("file{}", i)).unwrap();
+                    assert_eq!(file.write_at(0, &content(t, i)), content(t, i).len());
+                }
+                for i in (0..16).step_by(2) {
+                    assert!(dir.unlink(&format!("file{}", i)));
+                }
+                let mut buffer = vec![0u8; 4 * BLOCK_SZ];
+                for i in (1..16).step_by(2) {
+                    let file = dir.find(&format!("file{}", i)).unwrap();
+                    let len = file.read_at(0, &mut buffer);
+                    assert_eq!(buffer[..len], content(t, i)[..]);
+                }
+            })
+        })
+        .collect();
+    let lister = {
+        let root = root.clone();
+        std::thread::spawn(move || {
+            for _ in 0..200 {
+                for name in root.ls() {
+                    assert!(root.find(&name).unwrap().is_dir());
+                }
+            }
+        })
+    };
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    lister.join().unwrap();
+    easy_fs::set_block_cache_capacity(16);
+    let mut names = root.ls();
+    names.sort();
+    assert_eq!(names, vec!["dir0", "dir1", "dir2", "dir3"]);
+    for name in names {
+        assert_eq!(root.find(&name).unwrap().ls().len(), 8);
+    }
+    efs.lock().sync();
+    assert_eq!(efs.lock().fsck(false), vec![]);
+    device.power_cycle();
+}
@@ -25,29 +25,28 @@ impl Bitmap {
         }
     }
 
+    /// Allocate the first free bit. Only the block where it is set is
+    /// modified, so that the full blocks before are not logged.
     pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
         for block_id in 0..self.blocks {
-            let pos = get_block_cache(
+            let block_cache = get_block_cache(
                 block_id + self.start_block_id as usize,
                 Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |bitmap_block: &mut BitmapBlock| {
-                if let Some((bits64_pos, inner_pos)) = bitmap_block
+            );
+            let mut block_cache = block_cache.lock();
+            let free = block_cache.read(0, |bitmap_block: &BitmapBlock| {
+                bitmap_block
                     .iter()
                     .enumerate()
                     .find(|(_, bits64)| **bits64 != u64::MAX)
                     .map(|(bits64_pos, bits64)| (bits64_pos, bits64.trailing_ones() as usize))
-                {
-                    // modify cache
-                    bitmap_block[bits64_pos] |= 1u64 << inner_pos;
-                    Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos as usize)
-                } else {
-                    None
-                }
             });
-            if pos.is_some() {
-                return pos;
+            if let Some((bits64_pos, inner_pos)) = free {
+                // modify cache
+                block_cache.modify_logged(0, |bitmap_block: &mut BitmapBlock| {
+                    bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+                });
+                return Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos as usize);
             }
         }
         None
@@ -57,12 +56,37 @@ impl Bitmap {
         let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
         get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
             .lock()
-            .modify(0, |bitmap_block: &mut BitmapBlock| {
+            .modify_logged(0, |bitmap_block: &mut BitmapBlock| {
                 assert!(bitmap_block[bits64_pos] & (1u64 << inner_pos) > 0);
                 bitmap_block[bits64_pos] -= 1u64 << inner_pos;
             });
     }
 
+    pub fn is_allocated(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> bool {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .read(0, |bitmap_block: &BitmapBlock| {
+                bitmap_block[bits64_pos] & (1u64 << inner_pos) > 0
+            })
+    }
+
+    /// Allocate the given bit, which must be free.
+    pub fn mark(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .modify_logged(0, |bitmap_block: &mut BitmapBlock| {
+                assert!(bitmap_block[bits64_pos] & (1u64 << inner_pos) == 0);
+                bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+            });
+    }
+
+    /// Index of the block of the bitmap holding `bit`.
+    pub fn block_of(bit: usize) -> usize {
+        decomposition(bit).0
+    }
+
     pub fn maximum(&self) -> usize {
         self.blocks * BLOCK_BITS
     }
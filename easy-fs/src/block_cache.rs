@@ -1,26 +1,41 @@
-use super::{BlockDevice, BLOCK_SZ};
+use super::{BlockDevice, Mutex, BLOCK_SZ};
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
-use spin::Mutex;
 
+/// `cache` goes first to be aligned as the whole struct, on-disk structures
+/// with `u64` fields are referred to inside it.
+#[repr(C)]
 pub struct BlockCache {
     cache: [u8; BLOCK_SZ],
     block_id: usize,
     block_device: Arc<dyn BlockDevice>,
     modified: bool,
+    /// metadata modified in the running transaction, which must reach its
+    /// place on disk only after the journal holds a copy
+    logged: bool,
+    loaded: bool,
 }
 
 impl BlockCache {
-    /// Load a new BlockCache from disk.
+    /// Make a BlockCache to be loaded from disk by `load`.
     pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Self {
-        let mut cache = [0u8; BLOCK_SZ];
-        block_device.read_block(block_id, &mut cache);
         Self {
-            cache,
+            cache: [0u8; BLOCK_SZ],
             block_id,
             block_device,
             modified: false,
+            logged: false,
+            loaded: false,
+        }
+    }
+
+    /// Read the block from disk unless it has been.
+    fn load(&mut self) {
+        if !self.loaded {
+            self.loaded = true;
+            self.block_device.read_block(self.block_id, &mut self.cache);
         }
     }
 
@@ -57,12 +72,29 @@ impl BlockCache {
         f(self.get_mut(offset))
     }
 
+    /// Modify metadata, the block is left to the journal to be written back.
+    pub fn modify_logged<T, V>(&mut self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
+        self.logged = true;
+        self.modify(offset, f)
+    }
+
+    pub fn is_logged(&self) -> bool {
+        self.logged
+    }
+
+    /// Write back the block unless the journal is in charge of it.
     pub fn sync(&mut self) {
-        if self.modified {
+        if self.modified && !self.logged {
             self.modified = false;
             self.block_device.write_block(self.block_id, &self.cache);
         }
     }
+
+    /// Write back a logged block once its transaction is committed.
+    pub fn checkpoint(&mut self) {
+        self.logged = false;
+        self.sync();
+    }
 }
 
 impl Drop for BlockCache {
@@ -71,69 +103,155 @@ impl Drop for BlockCache {
     }
 }
 
+/// Default number of cached blocks.
 const BLOCK_CACHE_SIZE: usize = 16;
 
+/// Write-back cache of blocks. Modified blocks reach the device when they are
+/// evicted or on `block_cache_sync_all`, except the logged ones which are
+/// written back by the journal.
 pub struct BlockCacheManager {
+    capacity: usize,
+    /// from the least recently used to the most recently used
     queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    /// evicted blocks until they are written back, so that they are not read
+    /// from disk before
+    evicted: Vec<(usize, Arc<Mutex<BlockCache>>)>,
 }
 
 impl BlockCacheManager {
     pub fn new() -> Self {
         Self {
+            capacity: BLOCK_CACHE_SIZE,
             queue: VecDeque::new(),
+            evicted: Vec::new(),
         }
     }
 
+    /// Return the block and those evicted for it, which the caller writes
+    /// back by `write_back` once the manager is unlocked.
     pub fn get_block_cache(
         &mut self,
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
-    ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Arc::clone(&pair.1)
-        } else {
-            // substitute
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of BlockCache!");
-                }
+    ) -> (Arc<Mutex<BlockCache>>, Vec<Arc<Mutex<BlockCache>>>) {
+        if let Some(idx) = self.queue.iter().position(|pair| pair.0 == block_id) {
+            // move to the tail as the most recently used
+            let pair = self.queue.remove(idx).unwrap();
+            let block_cache = Arc::clone(&pair.1);
+            self.queue.push_back(pair);
+            return (block_cache, Vec::new());
+        }
+        // the cache grows beyond its capacity if all blocks are in use or
+        // logged, until the journal commits
+        let mut evicted = Vec::new();
+        while self.queue.len() >= self.capacity {
+            match self.evict() {
+                Some(block_cache) => evicted.push(block_cache),
+                None => break,
             }
-            // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(BlockCache::new(
+        }
+        // an evicted block not written back yet is taken back as it is
+        let block_cache = match self.evicted.iter().position(|pair| pair.0 == block_id) {
+            Some(idx) => self.evicted.swap_remove(idx).1,
+            // loaded by `get_block_cache` once the manager is unlocked
+            None => Arc::new(Mutex::new(BlockCache::new(
                 block_id,
                 Arc::clone(&block_device),
-            )));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
-            block_cache
-        }
+            ))),
+        };
+        self.queue.push_back((block_id, Arc::clone(&block_cache)));
+        (block_cache, evicted)
+    }
+
+    /// Drop the least recently used block nobody refers to from the queue,
+    /// return `None` if there is none. Logged blocks stay until they are committed.
+    fn evict(&mut self) -> Option<Arc<Mutex<BlockCache>>> {
+        let idx = self
+            .queue
+            .iter()
+            .position(|pair| Arc::strong_count(&pair.1) == 1 && !pair.1.lock().is_logged())?;
+        let pair = self.queue.remove(idx).unwrap();
+        let block_cache = Arc::clone(&pair.1);
+        self.evicted.push(pair);
+        Some(block_cache)
     }
 }
 
+/// Write back a block evicted by `BlockCacheManager::get_block_cache`.
+fn write_back(block_cache: Arc<Mutex<BlockCache>>) {
+    block_cache.lock().sync();
+    BLOCK_CACHE_MANAGER
+        .lock()
+        .evicted
+        .retain(|pair| !Arc::ptr_eq(&pair.1, &block_cache));
+}
+
 lazy_static! {
     pub static ref BLOCK_CACHE_MANAGER: Mutex<BlockCacheManager> =
         Mutex::new(BlockCacheManager::new());
 }
 
+/// Get the cached block, which is read from disk without holding up those
+/// looking for other blocks.
 pub fn get_block_cache(
     block_id: usize,
     block_device: Arc<dyn BlockDevice>,
 ) -> Arc<Mutex<BlockCache>> {
-    BLOCK_CACHE_MANAGER
+    let (block_cache, evicted) = BLOCK_CACHE_MANAGER
         .lock()
-        .get_block_cache(block_id, block_device)
+        .get_block_cache(block_id, block_device);
+    for evicted in evicted {
+        write_back(evicted);
+    }
+    block_cache.lock().load();
+    block_cache
 }
 
-pub fn block_cache_sync_all() {
+/// Set how many blocks are kept in the cache, a smaller capacity takes effect
+/// as new blocks are loaded.
+pub fn set_block_cache_capacity(capacity: usize) {
+    assert!(capacity > 0);
+    BLOCK_CACHE_MANAGER.lock().capacity = capacity;
+}
+
+/// All cached blocks, with the evicted ones not written back yet. They are
+/// locked one by one after the manager is unlocked, as whoever holds one may
+/// be about to look for another.
+fn cached_blocks() -> Vec<(usize, Arc<Mutex<BlockCache>>)> {
     let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
+    manager
+        .queue
+        .iter()
+        .chain(manager.evicted.iter())
+        .map(|(block_id, cache)| (*block_id, Arc::clone(cache)))
+        .collect()
+}
+
+/// Write all modified blocks back to their devices.
+pub fn block_cache_sync_all() {
+    for (_, cache) in cached_blocks() {
         cache.lock().sync();
     }
 }
+
+/// Logged blocks in the order of block ids.
+pub fn logged_blocks() -> Vec<(usize, Arc<Mutex<BlockCache>>)> {
+    let mut blocks: Vec<(usize, Arc<Mutex<BlockCache>>)> = cached_blocks()
+        .into_iter()
+        .filter(|(_, cache)| cache.lock().is_logged())
+        .collect();
+    blocks.sort_by_key(|(block_id, _)| *block_id);
+    blocks
+}
+
+/// Drop all cached blocks without writing them back, as a power cut would.
+pub fn block_cache_discard_all() {
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    for (_, cache) in manager.queue.iter() {
+        let mut cache = cache.lock();
+        cache.modified = false;
+        cache.logged = false;
+    }
+    manager.queue.clear();
+    manager.evicted.clear();
+}
@@ -0,0 +1,18 @@
+use spin::Mutex;
+
+fn no_clock() -> u64 {
+    0
+}
+
+static CLOCK: Mutex<fn() -> u64> = Mutex::new(no_clock);
+
+/// Set the clock for inode timestamps, which returns the current time in milliseconds.
+/// Timestamps are 0 until a clock is set.
+pub fn set_clock(clock: fn() -> u64) {
+    *CLOCK.lock() = clock;
+}
+
+pub(crate) fn now() -> u64 {
+    let clock = *CLOCK.lock();
+    clock()
+}
@@ -1,10 +1,10 @@
 use super::{
-    block_cache_sync_all, get_block_cache, Bitmap, BlockDevice, DiskInode, DiskInodeType, Inode,
-    SuperBlock,
+    block_cache_sync_all, get_block_cache, logged_blocks, now, Bitmap, BlockDevice, DiskInode,
+    DiskInodeType, Inode, Inodes, Journal, Mutex, SuperBlock, DIRENT_SZ, JOURNAL_BLOCKS,
 };
 use crate::BLOCK_SZ;
 use alloc::sync::Arc;
-use spin::Mutex;
+use alloc::vec::Vec;
 
 pub struct EasyFileSystem {
     pub block_device: Arc<dyn BlockDevice>,
@@ -12,35 +12,79 @@ pub struct EasyFileSystem {
     pub data_bitmap: Bitmap,
     inode_area_start_block: u32,
     data_area_start_block: u32,
+    journal: Journal,
+    /// size of a directory entry, which limits the length of names
+    dirent_size: usize,
+    /// whether new directories are hashed
+    hashed_dirs: bool,
+    /// data blocks freed, which are returned to the bitmap once a commit
+    /// makes it safe, so that they are not reused before
+    freed_blocks: Vec<u32>,
+    /// journal blocks reserved by the updates of the running transaction
+    reserved_blocks: usize,
+    inodes: Arc<Inodes>,
 }
 
 type DataBlock = [u8; BLOCK_SZ];
 
+/// Journal blocks an update may log at most, which are reserved as it starts.
+const UPDATE_BLOCKS: usize = 24;
+/// Data blocks a write or an allocation maps in one update, which stays
+/// within `UPDATE_BLOCKS` with the index blocks and bitmap blocks on the way.
+pub const UPDATE_DATA_BLOCKS: usize = 256;
+/// Blocks of the data bitmap a commit returns freed blocks to at most, which
+/// are left out of the reservations. The others wait for the next commit.
+const FREED_BITMAP_BLOCKS: usize = 8;
+/// Commit before an update once this many freed blocks wait to be returned.
+const FREED_LIMIT: usize = 1024;
+
 impl EasyFileSystem {
+    /// Format `block_device`, with directory entries of `dirent_size` bytes,
+    /// which is a power of two from `DIRENT_SZ` to `BLOCK_SZ`. Directories are
+    /// hashed if `hashed_dirs` is set, which leaves room for the header of a
+    /// bucket only if an entry is smaller than a block.
     pub fn create(
         block_device: Arc<dyn BlockDevice>,
         total_blocks: u32,
         inode_bitmap_blocks: u32,
+        dirent_size: u32,
+        hashed_dirs: bool,
     ) -> Arc<Mutex<Self>> {
-        // calculate block size of areas & create bitmaps
-        let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
+        assert!(
+            dirent_size.is_power_of_two()
+                && (DIRENT_SZ..=BLOCK_SZ).contains(&(dirent_size as usize)),
+            "Bad size of directory entries!"
+        );
+        assert!(
+            !hashed_dirs || (dirent_size as usize) < BLOCK_SZ,
+            "Directory entries too large to be hashed!"
+        );
+        // calculate block size of areas & create bitmaps, the journal follows the SuperBlock
+        let journal_blocks = JOURNAL_BLOCKS;
+        let inode_bitmap = Bitmap::new(1 + journal_blocks as usize, inode_bitmap_blocks as usize);
         let inode_num = inode_bitmap.maximum();
         let inode_area_blocks =
             ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
         let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks;
+        let data_total_blocks = total_blocks - 1 - journal_blocks - inode_total_blocks;
         let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
         let data_area_blocks = data_total_blocks - data_bitmap_blocks;
         let data_bitmap = Bitmap::new(
-            (1 + inode_bitmap_blocks + inode_area_blocks) as usize,
+            (1 + journal_blocks + inode_bitmap_blocks + inode_area_blocks) as usize,
             data_bitmap_blocks as usize,
         );
         let mut efs = Self {
             block_device: Arc::clone(&block_device),
             inode_bitmap,
             data_bitmap,
-            inode_area_start_block: 1 + inode_bitmap_blocks,
-            data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            inode_area_start_block: 1 + journal_blocks + inode_bitmap_blocks,
+            data_area_start_block: 1 + journal_blocks + inode_total_blocks + data_bitmap_blocks,
+            journal: Journal::new(1, journal_blocks as usize, Arc::clone(&block_device)),
+            dirent_size: dirent_size as usize,
+            hashed_dirs,
+            freed_blocks: Vec::new(),
+            reserved_blocks: 0,
+            inodes: Arc::new(Inodes::new()),
         };
         // clear all blocks
         for i in 0..total_blocks {
@@ -62,50 +106,96 @@ impl EasyFileSystem {
                     inode_area_blocks,
                     data_bitmap_blocks,
                     data_area_blocks,
+                    journal_blocks,
+                    dirent_size,
+                    hashed_dirs,
                 );
             },
         );
-        // write back immediately
+        efs.journal.format();
         // create a inode for root node "/"
         assert_eq!(efs.alloc_inode(), 0);
         let (root_inode_block_id, root_inode_offset) = efs.get_disk_inode_pos(0);
         get_block_cache(root_inode_block_id as usize, Arc::clone(&block_device))
             .lock()
             .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
-                disk_inode.initialize(DiskInodeType::Directory);
+                disk_inode.initialize(DiskInodeType::Directory, now());
+                if hashed_dirs {
+                    disk_inode.set_hashed();
+                }
             });
-        block_cache_sync_all();
-        Arc::new(Mutex::new(efs))
+        let efs = Arc::new(Mutex::new(efs));
+        // both "." and ".." of the root directory point to itself
+        Self::root_inode(&efs).initialize_dir(0);
+        // write back immediately
+        efs.lock().sync();
+        efs
     }
 
-    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
+    /// Open the filesystem on `block_device`, finishing the transaction in the
+    /// journal if the system stopped while it was written back. Return `None`
+    /// if it holds no image of this version of easy-fs.
+    pub fn open(block_device: Arc<dyn BlockDevice>) -> Option<Arc<Mutex<Self>>> {
         // read SuperBlock
-        get_block_cache(0, Arc::clone(&block_device))
-            .lock()
-            .read(0, |super_block: &SuperBlock| {
-                assert!(super_block.is_valid(), "Error loading EFS!");
+        let efs = get_block_cache(0, Arc::clone(&block_device)).lock().read(
+            0,
+            |super_block: &SuperBlock| {
+                if !super_block.is_valid() {
+                    return None;
+                }
+                let journal_blocks = super_block.journal_blocks;
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
-                let efs = Self {
-                    block_device,
-                    inode_bitmap: Bitmap::new(1, super_block.inode_bitmap_blocks as usize),
+                Some(Self {
+                    block_device: Arc::clone(&block_device),
+                    inode_bitmap: Bitmap::new(
+                        (1 + journal_blocks) as usize,
+                        super_block.inode_bitmap_blocks as usize,
+                    ),
                     data_bitmap: Bitmap::new(
-                        (1 + inode_total_blocks) as usize,
+                        (1 + journal_blocks + inode_total_blocks) as usize,
                         super_block.data_bitmap_blocks as usize,
                     ),
-                    inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
-                    data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
-                };
-                Arc::new(Mutex::new(efs))
-            })
+                    inode_area_start_block: 1 + journal_blocks + super_block.inode_bitmap_blocks,
+                    data_area_start_block: 1
+                        + journal_blocks
+                        + inode_total_blocks
+                        + super_block.data_bitmap_blocks,
+                    journal: Journal::new(1, journal_blocks as usize, Arc::clone(&block_device)),
+                    dirent_size: match super_block.dirent_size {
+                        0 => DIRENT_SZ,
+                        dirent_size => dirent_size as usize,
+                    },
+                    hashed_dirs: super_block.hashed_dirs != 0,
+                    freed_blocks: Vec::new(),
+                    reserved_blocks: 0,
+                    inodes: Arc::new(Inodes::new()),
+                })
+            },
+        )?;
+        efs.journal.replay();
+        Some(Arc::new(Mutex::new(efs)))
+    }
+
+    /// Read the SuperBlock, which is only written by `create`.
+    pub fn read_super_block<V>(&self, f: impl FnOnce(&SuperBlock) -> V) -> V {
+        get_block_cache(0, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, f)
+    }
+
+    /// The root directory, the same `Inode` as long as somebody refers to it.
+    pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Arc<Inode> {
+        let inodes = Arc::clone(&efs.lock().inodes);
+        Inode::get(0, efs, &inodes)
+    }
+
+    pub fn dirent_size(&self) -> usize {
+        self.dirent_size
     }
 
-    pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
-        let block_device = Arc::clone(&efs.lock().block_device);
-        // acquire efs lock temporarily
-        let (block_id, block_offset) = efs.lock().get_disk_inode_pos(0);
-        // release efs lock
-        Inode::new(block_id, block_offset, Arc::clone(efs), block_device)
+    pub fn hashed_dirs(&self) -> bool {
+        self.hashed_dirs
     }
 
     pub fn get_disk_inode_pos(&self, inode_id: u32) -> (u32, usize) {
@@ -126,12 +216,18 @@ impl EasyFileSystem {
         self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
     }
 
-    /// Return a block ID not ID in the data area.
-    pub fn alloc_data(&mut self) -> u32 {
-        self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
+    pub fn dealloc_inode(&mut self, inode_id: u32) {
+        self.inode_bitmap
+            .dealloc(&self.block_device, inode_id as usize)
     }
 
-    pub fn dealloc_data(&mut self, block_id: u32) {
+    /// Return a block ID not ID in the data area.
+    ///
+    /// The block is cleared here rather than when it is freed, as it may
+    /// still be in use on disk until the transaction freeing it is committed.
+    pub fn alloc_data(&mut self) -> u32 {
+        let block_id =
+            self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block;
         get_block_cache(block_id as usize, Arc::clone(&self.block_device))
             .lock()
             .modify(0, |data_block: &mut DataBlock| {
@@ -139,9 +235,68 @@ impl EasyFileSystem {
                     *p = 0;
                 })
             });
-        self.data_bitmap.dealloc(
-            &self.block_device,
-            (block_id - self.data_area_start_block) as usize,
-        )
+        block_id
+    }
+
+    pub fn dealloc_data(&mut self, block_id: u32) {
+        self.freed_blocks.push(block_id);
+    }
+
+    /// Commit the running transaction through the journal, returning the
+    /// blocks freed before to the bitmap along with it.
+    fn commit(&mut self) {
+        let mut bitmap_blocks = Vec::new();
+        let (data_bitmap, block_device) = (&self.data_bitmap, &self.block_device);
+        let data_area_start_block = self.data_area_start_block;
+        self.freed_blocks.retain(|block_id| {
+            let bit = (block_id - data_area_start_block) as usize;
+            let bitmap_block = Bitmap::block_of(bit);
+            if !bitmap_blocks.contains(&bitmap_block) {
+                if bitmap_blocks.len() == FREED_BITMAP_BLOCKS {
+                    return true;
+                }
+                bitmap_blocks.push(bitmap_block);
+            }
+            data_bitmap.dealloc(block_device, bit);
+            false
+        });
+        self.journal.commit(&logged_blocks());
+        self.reserved_blocks = 0;
+    }
+
+    /// Whether the running transaction should be committed before another
+    /// update starts. Without a journal there is no room to run out of.
+    fn is_full(&self) -> bool {
+        let capacity = self.journal.capacity();
+        let room = capacity.saturating_sub(FREED_BITMAP_BLOCKS);
+        self.freed_blocks.len() >= FREED_LIMIT
+            || (capacity > 0
+                && self.reserved_blocks > 0
+                && self.reserved_blocks + UPDATE_BLOCKS > room)
+    }
+
+    /// Reserve room in the running transaction for an update, return false
+    /// if it should be committed first.
+    pub fn reserve_update(&mut self) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.reserved_blocks += UPDATE_BLOCKS;
+        true
+    }
+
+    /// Commit if `reserve_update` found the running transaction full, once
+    /// the updates running are over.
+    pub fn commit_if_full(&mut self) {
+        if self.is_full() {
+            self.sync();
+        }
+    }
+
+    /// Write everything back, the file contents before the metadata which
+    /// refers to them.
+    pub fn sync(&mut self) {
+        block_cache_sync_all();
+        self.commit();
     }
 }
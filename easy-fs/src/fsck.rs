@@ -0,0 +1,614 @@
+use super::{
+    get_block_cache, hash_bucket, name_hash, DirEntry, DiskInode, EasyFileSystem, IndirectBlock,
+    BLOCK_SZ, INODE_DIRECT_COUNT, INODE_INDIRECT1_COUNT,
+};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter, Result};
+
+type DataBlock = [u8; BLOCK_SZ];
+
+/// An inconsistency found by `EasyFileSystem::fsck`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// the areas in the SuperBlock do not fit, nothing else is checked
+    BadLayout,
+    /// the root inode is free or not a directory, nothing else is checked
+    BadRoot,
+    /// a data block allocated in the bitmap which no inode refers to
+    LeakedBlock(u32),
+    /// a data block an inode refers to which is free in the bitmap
+    UnmarkedBlock(u32),
+    /// a data block referred to again by `inode_id`
+    DuplicateBlock { block_id: u32, inode_id: u32 },
+    /// a size the blocks of the inode do not cover, or which is not a whole
+    /// number of entries for a directory, or of blocks for a hashed one
+    SizeMismatch {
+        inode_id: u32,
+        size: u64,
+        fixed_size: u64,
+    },
+    /// an entry referring to a free inode or one out of the inode area
+    DanglingDirent {
+        dir_id: u32,
+        name: String,
+        inode_id: u32,
+    },
+    /// an entry with a name which is not terminated or not UTF-8, or one in
+    /// the place of a bucket header in a hashed directory
+    BadDirent { dir_id: u32, slot: usize },
+    /// headers of a hashed directory which count other than the entries
+    /// displaced from their buckets
+    BadHashIndex(u32),
+    /// an allocated inode no entry refers to
+    LeakedInode(u32),
+    /// a link count other than the number of entries referring to the inode
+    NlinkMismatch {
+        inode_id: u32,
+        nlink: u32,
+        links: u32,
+    },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Problem::BadLayout => write!(f, "the areas in the SuperBlock do not fit"),
+            Problem::BadRoot => write!(f, "the root inode is not an allocated directory"),
+            Problem::LeakedBlock(block_id) => write!(f, "block {} is leaked", block_id),
+            Problem::UnmarkedBlock(block_id) => {
+                write!(f, "block {} is in use but free in the bitmap", block_id)
+            }
+            Problem::DuplicateBlock { block_id, inode_id } => write!(
+                f,
+                "block {} is referred to again by inode {}",
+                block_id, inode_id
+            ),
+            Problem::SizeMismatch {
+                inode_id,
+                size,
+                fixed_size,
+            } => write!(
+                f,
+                "inode {} has size {} but its blocks hold {}",
+                inode_id, size, fixed_size
+            ),
+            Problem::DanglingDirent {
+                dir_id,
+                name,
+                inode_id,
+            } => write!(
+                f,
+                "entry {:?} in directory {} refers to unused inode {}",
+                name, dir_id, inode_id
+            ),
+            Problem::BadDirent { dir_id, slot } => {
+                write!(f, "entry {} in directory {} is corrupted", slot, dir_id)
+            }
+            Problem::BadHashIndex(dir_id) => write!(
+                f,
+                "directory {} does not count its displaced entries right",
+                dir_id
+            ),
+            Problem::LeakedInode(inode_id) => write!(f, "inode {} is leaked", inode_id),
+            Problem::NlinkMismatch {
+                inode_id,
+                nlink,
+                links,
+            } => write!(
+                f,
+                "inode {} has nlink {} but {} entries refer to it",
+                inode_id, nlink, links
+            ),
+        }
+    }
+}
+
+/// Where a block pointer is stored.
+#[derive(Clone, Copy)]
+enum Slot {
+    Direct(usize),
+    Indirect1,
+    Indirect2,
+    Indirect3,
+    /// an entry of an indirect block
+    Entry {
+        block_id: u32,
+        index: usize,
+    },
+}
+
+/// A pointer to a data or indirect block of an inode.
+#[derive(Clone, Copy)]
+struct BlockRef {
+    inode_id: u32,
+    slot: Slot,
+    block_id: u32,
+    /// false for an indirect block
+    is_data: bool,
+}
+
+impl EasyFileSystem {
+    /// Check the filesystem from the root down and report what is
+    /// inconsistent, fixing it if `repair` is set:
+    ///
+    /// - a size the blocks do not cover is cut down to what they cover
+    /// - dangling or corrupted entries are cleared
+    /// - the headers of a hashed directory count the entries where they are
+    /// - leaked inodes and blocks are freed, unmarked blocks are allocated
+    /// - a duplicate block is copied for each inode but the first one
+    /// - link counts are set to the number of entries
+    ///
+    /// The journal has been replayed by `open` before.
+    pub fn fsck(&mut self, repair: bool) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        let (total_blocks, data_area_blocks) = self.read_super_block(|super_block| {
+            (super_block.total_blocks, super_block.data_area_blocks)
+        });
+        let data_start = self.get_data_block_id(0);
+        let data_end = data_start as u64 + data_area_blocks as u64;
+        if data_end > total_blocks as u64 || data_area_blocks as usize > self.data_bitmap.maximum()
+        {
+            return vec![Problem::BadLayout];
+        }
+        let inode_count = self.inode_bitmap.maximum() as u32;
+        if !self.inode_bitmap.is_allocated(&self.block_device, 0)
+            || !self.read_inode(0, |disk_inode| disk_inode.is_dir())
+        {
+            return vec![Problem::BadRoot];
+        }
+        let is_data_block = |block_id: u32| block_id >= data_start && (block_id as u64) < data_end;
+
+        // walk the tree, counting the entries referring to each inode
+        let mut links: BTreeMap<u32, u32> = BTreeMap::new();
+        let mut refs: Vec<BlockRef> = Vec::new();
+        let mut dirs = vec![0u32];
+        links.insert(0, 0);
+        let slots_per_bucket = BLOCK_SZ / self.dirent_size();
+        while let Some(dir_id) = dirs.pop() {
+            let hashed = self.read_inode(dir_id, |disk_inode| disk_inode.is_hashed());
+            let dirents = self.inode_refs(dir_id, is_data_block, repair, &mut problems, &mut refs);
+            // entries which are kept, by slot
+            let mut kept = Vec::new();
+            for (slot, dirent) in dirents.iter() {
+                let slot = *slot;
+                if dirent.is_empty() {
+                    continue;
+                }
+                if !dirent.is_valid() || (hashed && slot % slots_per_bucket == 0) {
+                    problems.push(Problem::BadDirent { dir_id, slot });
+                    if repair {
+                        self.start_repair();
+                        self.write_dirent(dir_id, slot, &DirEntry::empty(self.dirent_size()));
+                    }
+                    continue;
+                }
+                let inode_id = dirent.inode_number();
+                if inode_id >= inode_count
+                    || !self
+                        .inode_bitmap
+                        .is_allocated(&self.block_device, inode_id as usize)
+                {
+                    problems.push(Problem::DanglingDirent {
+                        dir_id,
+                        name: String::from(dirent.name()),
+                        inode_id,
+                    });
+                    if repair {
+                        self.start_repair();
+                        self.write_dirent(dir_id, slot, &DirEntry::empty(self.dirent_size()));
+                    }
+                    continue;
+                }
+                kept.push((slot, dirent.name()));
+                let first_link = !links.contains_key(&inode_id);
+                *links.entry(inode_id).or_insert(0) += 1;
+                if first_link {
+                    if self.read_inode(inode_id, |disk_inode| disk_inode.is_dir()) {
+                        dirs.push(inode_id);
+                    } else {
+                        // a file has no entries, only blocks
+                        self.inode_refs(inode_id, is_data_block, repair, &mut problems, &mut refs);
+                    }
+                }
+            }
+            if hashed {
+                self.check_hash_index(dir_id, &dirents, &kept, repair, &mut problems);
+            }
+        }
+
+        // inodes
+        for inode_id in 0..inode_count {
+            if !self
+                .inode_bitmap
+                .is_allocated(&self.block_device, inode_id as usize)
+            {
+                continue;
+            }
+            match links.get(&inode_id) {
+                None => {
+                    problems.push(Problem::LeakedInode(inode_id));
+                    if repair {
+                        self.start_repair();
+                        self.dealloc_inode(inode_id);
+                    }
+                }
+                Some(&links) => {
+                    let nlink = self.read_inode(inode_id, |disk_inode| disk_inode.nlink);
+                    if nlink != links {
+                        problems.push(Problem::NlinkMismatch {
+                            inode_id,
+                            nlink,
+                            links,
+                        });
+                        if repair {
+                            self.start_repair();
+                            self.modify_inode(inode_id, |disk_inode| disk_inode.nlink = links);
+                        }
+                    }
+                }
+            }
+        }
+
+        // blocks, the first reference to a block owns it
+        let mut owned = vec![false; data_area_blocks as usize];
+        let mut duplicates = Vec::new();
+        for block_ref in refs.iter() {
+            let bit = (block_ref.block_id - data_start) as usize;
+            if owned[bit] {
+                problems.push(Problem::DuplicateBlock {
+                    block_id: block_ref.block_id,
+                    inode_id: block_ref.inode_id,
+                });
+                duplicates.push(*block_ref);
+            }
+            owned[bit] = true;
+        }
+        for (bit, owned) in owned.into_iter().enumerate() {
+            let block_id = data_start + bit as u32;
+            match (
+                self.data_bitmap.is_allocated(&self.block_device, bit),
+                owned,
+            ) {
+                (true, false) => {
+                    problems.push(Problem::LeakedBlock(block_id));
+                    if repair {
+                        self.start_repair();
+                        self.data_bitmap.dealloc(&self.block_device, bit);
+                    }
+                }
+                (false, true) => {
+                    problems.push(Problem::UnmarkedBlock(block_id));
+                    if repair {
+                        self.start_repair();
+                        self.data_bitmap.mark(&self.block_device, bit);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if repair {
+            // the bitmap is right by now, so copies can be allocated
+            let mut copies: BTreeMap<u32, u32> = BTreeMap::new();
+            for mut block_ref in duplicates {
+                // the pointer may be in an indirect block which has been copied before
+                if let Slot::Entry { block_id, index } = block_ref.slot {
+                    if let Some(copy) = copies.get(&block_id) {
+                        block_ref.slot = Slot::Entry {
+                            block_id: *copy,
+                            index,
+                        };
+                    }
+                }
+                self.start_repair();
+                let copy = self.copy_block(block_ref.block_id);
+                self.write_block_ref(&block_ref, copy);
+                copies.insert(block_ref.block_id, copy);
+            }
+            self.sync();
+        }
+        problems
+    }
+
+    /// Reserve room in the running transaction for a repair, committing the
+    /// repairs before if it is full. Each repair leaves the image consistent
+    /// enough to be checked again, so they are committed in several
+    /// transactions.
+    fn start_repair(&mut self) {
+        if !self.reserve_update() {
+            self.sync();
+            assert!(self.reserve_update());
+        }
+    }
+
+    fn read_inode<V>(&self, inode_id: u32, f: impl FnOnce(&DiskInode) -> V) -> V {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(block_offset, f)
+    }
+
+    fn modify_inode<V>(&self, inode_id: u32, f: impl FnOnce(&mut DiskInode) -> V) -> V {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify_logged(block_offset, f)
+    }
+
+    /// Collect the block pointers of an inode into `refs` and check its size
+    /// against them, then return the entries in the blocks if it is a directory.
+    ///
+    /// Pointers are followed in order until one is out of the data area, the
+    /// size is cut down to the data blocks before it, and the pointers beyond
+    /// the size are dropped. A pointer of 0 is a hole in a file, but is out
+    /// of the data area for a directory.
+    fn inode_refs(
+        &mut self,
+        inode_id: u32,
+        is_data_block: impl Fn(u32) -> bool,
+        repair: bool,
+        problems: &mut Vec<Problem>,
+        refs: &mut Vec<BlockRef>,
+    ) -> Vec<(usize, DirEntry)> {
+        let mut found = Vec::new();
+        let (size, is_dir, hashed, covered) = self.read_inode(inode_id, |disk_inode| {
+            (
+                disk_inode.size(),
+                disk_inode.is_dir(),
+                disk_inode.is_dir() && disk_inode.is_hashed(),
+                self.walk_blocks(
+                    inode_id,
+                    disk_inode,
+                    disk_inode.data_blocks() as usize,
+                    &is_data_block,
+                    &mut found,
+                ),
+            )
+        });
+        let mut fixed_size = size.min((covered * BLOCK_SZ) as u64);
+        if hashed {
+            fixed_size -= fixed_size % BLOCK_SZ as u64;
+        } else if is_dir {
+            fixed_size -= fixed_size % self.dirent_size() as u64;
+        }
+        if fixed_size != size {
+            problems.push(Problem::SizeMismatch {
+                inode_id,
+                size,
+                fixed_size,
+            });
+            // walk again up to what is kept
+            let needed = DiskInode::total_data_blocks(fixed_size);
+            found.clear();
+            self.read_inode(inode_id, |disk_inode| {
+                self.walk_blocks(
+                    inode_id,
+                    disk_inode,
+                    needed as usize,
+                    &is_data_block,
+                    &mut found,
+                )
+            });
+            if repair {
+                self.start_repair();
+                self.modify_inode(inode_id, |disk_inode| {
+                    disk_inode.set_size(fixed_size);
+                    disk_inode.forget_blocks(needed, &is_data_block, &self.block_device);
+                });
+            }
+        }
+        refs.extend_from_slice(&found);
+        if !is_dir {
+            return Vec::new();
+        }
+        let mut dirents = Vec::new();
+        let dirent_size = self.dirent_size();
+        let per_block = BLOCK_SZ / dirent_size;
+        let entries = fixed_size as usize / dirent_size;
+        for (i, block_ref) in found
+            .iter()
+            .filter(|block_ref| block_ref.is_data)
+            .enumerate()
+        {
+            get_block_cache(block_ref.block_id as usize, Arc::clone(&self.block_device))
+                .lock()
+                .read(0, |data_block: &DataBlock| {
+                    for j in 0..per_block.min(entries - i * per_block) {
+                        let dirent = DirEntry::from_bytes(
+                            &data_block[j * dirent_size..(j + 1) * dirent_size],
+                        );
+                        dirents.push((i * per_block + j, dirent));
+                    }
+                });
+        }
+        dirents
+    }
+
+    /// Check the headers of a hashed directory against the entries `kept`,
+    /// each one is displaced if it is out of its bucket.
+    fn check_hash_index(
+        &mut self,
+        dir_id: u32,
+        dirents: &[(usize, DirEntry)],
+        kept: &[(usize, &str)],
+        repair: bool,
+        problems: &mut Vec<Problem>,
+    ) {
+        let slots_per_bucket = BLOCK_SZ / self.dirent_size();
+        let buckets = dirents.len() / slots_per_bucket;
+        let mut displaced = vec![0u32; buckets];
+        for (slot, name) in kept {
+            let home = hash_bucket(name_hash(name), buckets);
+            if slot / slots_per_bucket != home {
+                displaced[home] += 1;
+            }
+        }
+        let wrong: Vec<usize> = (0..buckets)
+            .filter(|bucket| {
+                let header = &dirents[bucket * slots_per_bucket].1;
+                !header.is_empty() || header.inode_number() != displaced[*bucket]
+            })
+            .collect();
+        if wrong.is_empty() {
+            return;
+        }
+        problems.push(Problem::BadHashIndex(dir_id));
+        if repair {
+            for bucket in wrong {
+                self.start_repair();
+                let header = DirEntry::new("", displaced[bucket], self.dirent_size());
+                self.write_dirent(dir_id, bucket * slots_per_bucket, &header);
+            }
+        }
+    }
+
+    /// Push the pointers of the first `data_blocks` data blocks of
+    /// `disk_inode` and of the indirect blocks on the way, stopping at the
+    /// first one out of the data area, and return the number of data blocks
+    /// before it.
+    fn walk_blocks(
+        &self,
+        inode_id: u32,
+        disk_inode: &DiskInode,
+        data_blocks: usize,
+        is_data_block: &impl Fn(u32) -> bool,
+        found: &mut Vec<BlockRef>,
+    ) -> usize {
+        let holes = !disk_inode.is_dir();
+        // whether a block is pushed to walk below it, None if it is out of the data area
+        let mut push = |slot: Slot, block_id: u32, is_data: bool| {
+            if holes && block_id == 0 {
+                Some(false)
+            } else if is_data_block(block_id) {
+                found.push(BlockRef {
+                    inode_id,
+                    slot,
+                    block_id,
+                    is_data,
+                });
+                Some(true)
+            } else {
+                None
+            }
+        };
+        for (i, block_id) in disk_inode
+            .direct
+            .iter()
+            .enumerate()
+            .take(data_blocks.min(INODE_DIRECT_COUNT))
+        {
+            if push(Slot::Direct(i), *block_id, true).is_none() {
+                return i;
+            }
+        }
+        let mut start = INODE_DIRECT_COUNT;
+        for (levels, slot, root) in [
+            (1, Slot::Indirect1, disk_inode.indirect1),
+            (2, Slot::Indirect2, disk_inode.indirect2),
+            (3, Slot::Indirect3, disk_inode.indirect3),
+        ] {
+            if data_blocks <= start {
+                break;
+            }
+            let capacity = INODE_INDIRECT1_COUNT.pow(levels);
+            let walked = match push(slot, root, false) {
+                None => Err(0),
+                Some(false) => Ok(()),
+                Some(true) => {
+                    self.walk_indirect(root, levels, (data_blocks - start).min(capacity), &mut push)
+                }
+            };
+            if let Err(stop) = walked {
+                return start + stop;
+            }
+            start += capacity;
+        }
+        data_blocks
+    }
+
+    /// Push the pointers in the indirect block `block_id` with `levels` levels
+    /// of indirect blocks below it and including it, down to `count` data
+    /// blocks, as for `walk_blocks`. Return the number of data blocks before
+    /// a pointer out of the data area as the error.
+    fn walk_indirect(
+        &self,
+        block_id: u32,
+        levels: u32,
+        count: usize,
+        push: &mut impl FnMut(Slot, u32, bool) -> Option<bool>,
+    ) -> core::result::Result<(), usize> {
+        let indirect = get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |indirect: &IndirectBlock| *indirect);
+        // each entry covers `span` data blocks
+        let span = INODE_INDIRECT1_COUNT.pow(levels - 1);
+        for (index, entry) in indirect.iter().enumerate() {
+            let start = index * span;
+            if start >= count {
+                break;
+            }
+            match push(Slot::Entry { block_id, index }, *entry, levels == 1) {
+                None => return Err(start),
+                Some(true) if levels > 1 => self
+                    .walk_indirect(*entry, levels - 1, (count - start).min(span), push)
+                    .map_err(|stop| start + stop)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn write_dirent(&self, dir_id: u32, slot: usize, dirent: &DirEntry) {
+        let block_id = self.read_inode(dir_id, |disk_inode| {
+            disk_inode.get_block_id(
+                (slot * self.dirent_size() / BLOCK_SZ) as u32,
+                &self.block_device,
+            )
+        });
+        let offset = slot * self.dirent_size() % BLOCK_SZ;
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify_logged(0, |data_block: &mut DataBlock| {
+                data_block[offset..offset + self.dirent_size()].copy_from_slice(dirent.as_bytes());
+            });
+    }
+
+    /// Allocate a block with the content of `block_id`.
+    fn copy_block(&mut self, block_id: u32) -> u32 {
+        let copy = self.alloc_data();
+        let content = get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |data_block: &DataBlock| *data_block);
+        get_block_cache(copy as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify_logged(0, |data_block: &mut DataBlock| *data_block = content);
+        copy
+    }
+
+    /// Point `block_ref` to `block_id` instead.
+    fn write_block_ref(&self, block_ref: &BlockRef, block_id: u32) {
+        match block_ref.slot {
+            Slot::Direct(i) => self.modify_inode(block_ref.inode_id, |disk_inode| {
+                disk_inode.direct[i] = block_id
+            }),
+            Slot::Indirect1 => self.modify_inode(block_ref.inode_id, |disk_inode| {
+                disk_inode.indirect1 = block_id
+            }),
+            Slot::Indirect2 => self.modify_inode(block_ref.inode_id, |disk_inode| {
+                disk_inode.indirect2 = block_id
+            }),
+            Slot::Indirect3 => self.modify_inode(block_ref.inode_id, |disk_inode| {
+                disk_inode.indirect3 = block_id
+            }),
+            Slot::Entry {
+                block_id: indirect,
+                index,
+            } => get_block_cache(indirect as usize, Arc::clone(&self.block_device))
+                .lock()
+                .modify_logged(0, |indirect: &mut IndirectBlock| indirect[index] = block_id),
+        }
+    }
+}
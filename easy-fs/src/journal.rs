@@ -0,0 +1,158 @@
+use super::{get_block_cache, BlockCache, BlockDevice, Mutex, BLOCK_SZ};
+use alloc::sync::Arc;
+
+const JOURNAL_MAGIC: u32 = 0x4a524e4c;
+/// Blocks one transaction can hold, as many as the header has room for.
+const JOURNAL_CAPACITY: usize = (BLOCK_SZ - 12) / 4;
+/// The header followed by a copy of each block of a transaction.
+pub const JOURNAL_BLOCKS: u32 = 1 + JOURNAL_CAPACITY as u32;
+
+type DataBlock = [u8; BLOCK_SZ];
+
+/// The first block of the journal. Writing it with a non-zero `count` commits
+/// the transaction whose blocks have been copied after it, writing it with
+/// zero `count` ends the transaction once the blocks are in their places.
+#[repr(C)]
+struct JournalHeader {
+    magic: u32,
+    count: u32,
+    /// checksum of the targets and the copies, a header written without its
+    /// copies is not replayed
+    checksum: u32,
+    targets: [u32; JOURNAL_CAPACITY],
+}
+
+impl JournalHeader {
+    fn empty() -> Self {
+        Self {
+            magic: JOURNAL_MAGIC,
+            count: 0,
+            checksum: 0,
+            targets: [0; JOURNAL_CAPACITY],
+        }
+    }
+    fn as_bytes(&self) -> &DataBlock {
+        unsafe { &*(self as *const Self as *const DataBlock) }
+    }
+    fn as_bytes_mut(&mut self) -> &mut DataBlock {
+        unsafe { &mut *(self as *mut Self as *mut DataBlock) }
+    }
+}
+
+/// FNV-1a, continued from `hash`.
+fn checksum(hash: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+const CHECKSUM_INIT: u32 = 0x811c_9dc5;
+
+/// A write-ahead journal of metadata blocks, which is absent if `blocks` is zero.
+///
+/// Metadata updates mark their blocks as logged in the block cache. On commit
+/// the logged blocks are copied into the journal and committed by the header,
+/// then written to their places. If the system stops in between, the copies
+/// are written again by `replay` when the filesystem is opened.
+pub struct Journal {
+    start_block: usize,
+    blocks: usize,
+    block_device: Arc<dyn BlockDevice>,
+}
+
+impl Journal {
+    pub fn new(start_block: usize, blocks: usize, block_device: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            start_block,
+            blocks,
+            block_device,
+        }
+    }
+
+    /// Number of blocks a transaction can hold.
+    pub fn capacity(&self) -> usize {
+        self.blocks.saturating_sub(1)
+    }
+
+    /// Write an empty header.
+    pub fn format(&self) {
+        if self.blocks > 0 {
+            self.write_header(&JournalHeader::empty());
+        }
+    }
+
+    fn write_header(&self, header: &JournalHeader) {
+        self.block_device
+            .write_block(self.start_block, header.as_bytes());
+    }
+
+    /// Commit the logged blocks and write them back. Updates and repairs
+    /// reserve their room, so a transaction always fits into the journal.
+    pub fn commit(&self, blocks: &[(usize, Arc<Mutex<BlockCache>>)]) {
+        if self.blocks == 0 {
+            for (_, cache) in blocks {
+                cache.lock().checkpoint();
+            }
+            return;
+        }
+        if blocks.is_empty() {
+            return;
+        }
+        assert!(
+            blocks.len() <= self.capacity(),
+            "transaction of {} blocks beyond the journal",
+            blocks.len()
+        );
+        let mut header = JournalHeader::empty();
+        let mut hash = CHECKSUM_INIT;
+        for (i, (block_id, cache)) in blocks.iter().enumerate() {
+            header.targets[i] = *block_id as u32;
+            hash = checksum(hash, &header.targets[i].to_le_bytes());
+            cache.lock().read(0, |data: &DataBlock| {
+                hash = checksum(hash, data);
+                self.block_device
+                    .write_block(self.start_block + 1 + i, data);
+            });
+        }
+        header.count = blocks.len() as u32;
+        header.checksum = hash;
+        self.write_header(&header);
+        for (_, cache) in blocks {
+            cache.lock().checkpoint();
+        }
+        self.write_header(&JournalHeader::empty());
+    }
+
+    /// Finish the transaction committed before the system stopped, if any.
+    /// Return the number of blocks written again.
+    pub fn replay(&self) -> usize {
+        if self.blocks == 0 {
+            return 0;
+        }
+        let mut header = JournalHeader::empty();
+        self.block_device
+            .read_block(self.start_block, header.as_bytes_mut());
+        let count = header.count as usize;
+        if header.magic != JOURNAL_MAGIC || count == 0 || count > self.capacity() {
+            return 0;
+        }
+        let mut copies = alloc::vec![[0u8; BLOCK_SZ]; count];
+        let mut hash = CHECKSUM_INIT;
+        for (i, copy) in copies.iter_mut().enumerate() {
+            self.block_device.read_block(self.start_block + 1 + i, copy);
+            hash = checksum(hash, &header.targets[i].to_le_bytes());
+            hash = checksum(hash, copy);
+        }
+        if hash != header.checksum {
+            // the copies were not written completely before the system stopped
+            return 0;
+        }
+        for (i, copy) in copies.iter().enumerate() {
+            let cache = get_block_cache(header.targets[i] as usize, Arc::clone(&self.block_device));
+            let mut cache = cache.lock();
+            cache.modify(0, |data: &mut DataBlock| data.copy_from_slice(copy));
+            cache.sync();
+        }
+        self.write_header(&JournalHeader::empty());
+        count
+    }
+}
@@ -1,17 +1,23 @@
 use super::{get_block_cache, BlockDevice, BLOCK_SZ};
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::convert::TryInto;
 use core::fmt::{Debug, Formatter, Result};
 
-const EFS_MAGIC: u32 = 0x3b800001;
-const INODE_DIRECT_COUNT: usize = 28;
-const NAME_LENGTH_LIMIT: usize = 27;
-const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
+/// Changed along with the layout of `DiskInode`, so that images of an older
+/// version are rejected rather than misread.
+const EFS_MAGIC: u32 = 0x3b800003;
+pub const INODE_DIRECT_COUNT: usize = 19;
+pub const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
 const INODE_INDIRECT2_COUNT: usize = INODE_INDIRECT1_COUNT * INODE_INDIRECT1_COUNT;
+const INODE_INDIRECT3_COUNT: usize = INODE_INDIRECT2_COUNT * INODE_INDIRECT1_COUNT;
 const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
-const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
-#[allow(unused)]
+pub const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
 const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
+const INDIRECT3_BOUND: usize = INDIRECT2_BOUND + INODE_INDIRECT3_COUNT;
+/// Largest size of a file, about 1GiB, which the triple indirect block limits.
+pub const MAX_FILE_SIZE: u64 = (INDIRECT3_BOUND * BLOCK_SZ) as u64;
 
 #[repr(C)]
 pub struct SuperBlock {
@@ -21,6 +27,14 @@ pub struct SuperBlock {
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
     pub data_area_blocks: u32,
+    /// zero in images made before the journal, which have none
+    pub journal_blocks: u32,
+    /// size of a directory entry, zero in images made before it was
+    /// recorded, whose entries are `DIRENT_SZ` bytes
+    pub dirent_size: u32,
+    /// nonzero if new directories are hashed, zero in images made before
+    /// which have linear directories only
+    pub hashed_dirs: u32,
 }
 
 impl Debug for SuperBlock {
@@ -31,11 +45,15 @@ impl Debug for SuperBlock {
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
             .field("data_area_blocks", &self.data_area_blocks)
+            .field("journal_blocks", &self.journal_blocks)
+            .field("dirent_size", &self.dirent_size)
+            .field("hashed_dirs", &self.hashed_dirs)
             .finish()
     }
 }
 
 impl SuperBlock {
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize(
         &mut self,
         total_blocks: u32,
@@ -43,6 +61,9 @@ impl SuperBlock {
         inode_area_blocks: u32,
         data_bitmap_blocks: u32,
         data_area_blocks: u32,
+        journal_blocks: u32,
+        dirent_size: u32,
+        hashed_dirs: bool,
     ) {
         *self = Self {
             magic: EFS_MAGIC,
@@ -51,6 +72,9 @@ impl SuperBlock {
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            journal_blocks,
+            dirent_size,
+            hashed_dirs: hashed_dirs as u32,
         }
     }
     pub fn is_valid(&self) -> bool {
@@ -58,32 +82,93 @@ impl SuperBlock {
     }
 }
 
-#[derive(PartialEq)]
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum DiskInodeType {
     File,
     Directory,
 }
 
-type IndirectBlock = [u32; BLOCK_SZ / 4];
+pub type IndirectBlock = [u32; BLOCK_SZ / 4];
 type DataBlock = [u8; BLOCK_SZ];
 
+/// An inode, whose data blocks are found through `direct`, then the single,
+/// double and triple indirect blocks in turn.
+///
+/// The size is split in two halves as `size_hi` and `indirect3` have taken
+/// the place of reserved words, which are zero in images made before.
 #[repr(C)]
+#[derive(Clone)]
 pub struct DiskInode {
-    pub size: u32,
+    size: u32,
     pub direct: [u32; INODE_DIRECT_COUNT],
     pub indirect1: u32,
     pub indirect2: u32,
+    /// number of directory entries referring to this inode,
+    /// a directory is also referred by its own "." and the ".." of each sub-directory
+    pub nlink: u32,
+    /// permission bits, such as 0o755
+    pub mode: u16,
     type_: DiskInodeType,
+    flags: u8,
+    /// time of last access, in milliseconds of the clock set by `set_clock`
+    pub atime: u64,
+    /// time of last modification of the content
+    pub mtime: u64,
+    /// time of last change of the inode itself, including the content
+    pub ctime: u64,
+    size_hi: u32,
+    pub indirect3: u32,
 }
 
+/// A read stores the time of access again once the stored one is this old,
+/// one day in milliseconds.
+const ATIME_INTERVAL: u64 = 24 * 60 * 60 * 1000;
+
+pub const DEFAULT_FILE_MODE: u16 = 0o644;
+pub const DEFAULT_DIR_MODE: u16 = 0o755;
+/// The entries of the directory are placed in buckets by the hash of their
+/// names, see `hash_bucket`.
+const DIR_HASHED: u8 = 1;
+
 impl DiskInode {
-    /// indirect1 and indirect2 block are allocated only when they are needed.
-    pub fn initialize(&mut self, type_: DiskInodeType) {
-        self.size = 0;
+    /// indirect1, indirect2 and indirect3 block are allocated only when they are needed.
+    pub fn initialize(&mut self, type_: DiskInodeType, now: u64) {
+        self.set_size(0);
         self.direct.iter_mut().for_each(|v| *v = 0);
         self.indirect1 = 0;
         self.indirect2 = 0;
+        self.indirect3 = 0;
+        (self.nlink, self.mode) = match type_ {
+            DiskInodeType::File => (1, DEFAULT_FILE_MODE),
+            DiskInodeType::Directory => (2, DEFAULT_DIR_MODE),
+        };
         self.type_ = type_;
+        self.flags = 0;
+        self.atime = now;
+        self.mtime = now;
+        self.ctime = now;
+    }
+    pub fn size(&self) -> u64 {
+        (self.size_hi as u64) << 32 | self.size as u64
+    }
+    pub fn set_size(&mut self, size: u64) {
+        assert!(size <= MAX_FILE_SIZE);
+        self.size = size as u32;
+        self.size_hi = (size >> 32) as u32;
+    }
+    /// The content has been modified, which is also a change to the inode.
+    pub fn touch_modified(&mut self, now: u64) {
+        self.mtime = now;
+        self.ctime = now;
+    }
+    /// Whether a read at `now` should store the time of access, which is
+    /// done only for the first read after a change or once a day, as with
+    /// relatime, so that reading seldom updates the inode.
+    pub fn needs_atime(&self, now: u64) -> bool {
+        self.atime <= self.mtime
+            || self.atime <= self.ctime
+            || now.saturating_sub(self.atime) >= ATIME_INTERVAL
     }
     pub fn is_dir(&self) -> bool {
         self.type_ == DiskInodeType::Directory
@@ -92,203 +177,158 @@ impl DiskInode {
     pub fn is_file(&self) -> bool {
         self.type_ == DiskInodeType::File
     }
+    pub fn is_hashed(&self) -> bool {
+        self.flags & DIR_HASHED != 0
+    }
+    /// Make a fresh directory hashed, before it has any entries.
+    pub fn set_hashed(&mut self) {
+        assert!(self.is_dir() && self.size() == 0);
+        self.flags |= DIR_HASHED;
+    }
     /// Return block number correspond to size.
     pub fn data_blocks(&self) -> u32 {
-        Self::_data_blocks(self.size)
-    }
-    fn _data_blocks(size: u32) -> u32 {
-        (size + BLOCK_SZ as u32 - 1) / BLOCK_SZ as u32
-    }
-    /// Return number of blocks needed include indirect1/2.
-    pub fn total_blocks(size: u32) -> u32 {
-        let data_blocks = Self::_data_blocks(size) as usize;
-        let mut total = data_blocks as usize;
-        // indirect1
-        if data_blocks > INODE_DIRECT_COUNT {
-            total += 1;
-        }
-        // indirect2
-        if data_blocks > INDIRECT1_BOUND {
-            total += 1;
-            // sub indirect1
-            total +=
-                (data_blocks - INDIRECT1_BOUND + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+        Self::_data_blocks(self.size())
+    }
+    /// Return the number of data blocks for `size`, not counting indirect1/2/3.
+    pub fn total_data_blocks(size: u64) -> u32 {
+        Self::_data_blocks(size)
+    }
+    fn _data_blocks(size: u64) -> u32 {
+        ((size + BLOCK_SZ as u64 - 1) / BLOCK_SZ as u64) as u32
+    }
+    /// The pointer at `position`, counting the direct ones first and then
+    /// `indirect1`, `indirect2` and `indirect3`, as in `roots_within`.
+    fn root(&self, position: usize) -> u32 {
+        match position.checked_sub(INODE_DIRECT_COUNT) {
+            None => self.direct[position],
+            Some(0) => self.indirect1,
+            Some(1) => self.indirect2,
+            Some(2) => self.indirect3,
+            _ => panic!("No pointer at {} in an inode!", position),
         }
-        total as u32
     }
-    pub fn blocks_num_needed(&self, new_size: u32) -> u32 {
-        assert!(new_size >= self.size);
-        Self::total_blocks(new_size) - Self::total_blocks(self.size)
+    fn root_mut(&mut self, position: usize) -> &mut u32 {
+        match position.checked_sub(INODE_DIRECT_COUNT) {
+            None => &mut self.direct[position],
+            Some(0) => &mut self.indirect1,
+            Some(1) => &mut self.indirect2,
+            Some(2) => &mut self.indirect3,
+            _ => panic!("No pointer at {} in an inode!", position),
+        }
     }
+    /// Return the block of data block `inner_id`, or 0 if it is in a hole.
     pub fn get_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
         let inner_id = inner_id as usize;
-        if inner_id < INODE_DIRECT_COUNT {
-            self.direct[inner_id]
-        } else if inner_id < INDIRECT1_BOUND {
-            get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect_block: &IndirectBlock| {
-                    indirect_block[inner_id - INODE_DIRECT_COUNT]
-                })
-        } else {
-            let last = inner_id - INDIRECT1_BOUND;
-            let indirect1 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect2: &IndirectBlock| {
-                    indirect2[last / INODE_INDIRECT1_COUNT]
-                });
-            get_block_cache(indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect1: &IndirectBlock| {
-                    indirect1[last % INODE_INDIRECT1_COUNT]
-                })
+        let (position, levels, from, _) = roots_within(inner_id, inner_id + 1)
+            .next()
+            .expect("Block out of the triple indirect block!");
+        // walk down, the entries of a level each cover `span` blocks
+        let mut block_id = self.root(position);
+        for level in (0..levels).rev() {
+            if block_id == 0 {
+                break;
+            }
+            let span = INODE_INDIRECT1_COUNT.pow(level);
+            block_id = read_indirect(block_id, block_device)[from / span % INODE_INDIRECT1_COUNT];
         }
+        block_id
+    }
+    /// Return the number of blocks to allocate so that the data blocks
+    /// `start..end` are no holes, including the index blocks on the way.
+    pub fn blocks_to_map(&self, start: u32, end: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
+        roots_within(start as usize, end as usize)
+            .map(|(position, levels, from, to)| {
+                holes_below(self.root(position), levels, from, to, block_device)
+            })
+            .sum()
     }
-    pub fn increase_size(
+    /// Fill the holes among the data blocks `start..end` with `new_blocks`,
+    /// as many as `blocks_to_map` returns. The size is not changed.
+    pub fn map_blocks(
         &mut self,
-        new_size: u32,
+        start: u32,
+        end: u32,
         new_blocks: Vec<u32>,
         block_device: &Arc<dyn BlockDevice>,
     ) {
-        let mut current_blocks = self.data_blocks();
-        self.size = new_size;
-        let mut total_blocks = self.data_blocks();
         let mut new_blocks = new_blocks.into_iter();
-        // fill direct
-        while current_blocks < total_blocks.min(INODE_DIRECT_COUNT as u32) {
-            self.direct[current_blocks as usize] = new_blocks.next().unwrap();
-            current_blocks += 1;
-        }
-        // alloc indirect1
-        if total_blocks > INODE_DIRECT_COUNT as u32 {
-            if current_blocks == INODE_DIRECT_COUNT as u32 {
-                self.indirect1 = new_blocks.next().unwrap();
-            }
-            current_blocks -= INODE_DIRECT_COUNT as u32;
-            total_blocks -= INODE_DIRECT_COUNT as u32;
-        } else {
-            return;
-        }
-        // fill indirect1
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-                while current_blocks < total_blocks.min(INODE_INDIRECT1_COUNT as u32) {
-                    indirect1[current_blocks as usize] = new_blocks.next().unwrap();
-                    current_blocks += 1;
-                }
-            });
-        // alloc indirect2
-        if total_blocks > INODE_INDIRECT1_COUNT as u32 {
-            if current_blocks == INODE_INDIRECT1_COUNT as u32 {
-                self.indirect2 = new_blocks.next().unwrap();
-            }
-            current_blocks -= INODE_INDIRECT1_COUNT as u32;
-            total_blocks -= INODE_INDIRECT1_COUNT as u32;
-        } else {
-            return;
+        for (position, levels, from, to) in roots_within(start as usize, end as usize) {
+            fill_holes(
+                self.root_mut(position),
+                levels,
+                from,
+                to,
+                &mut new_blocks,
+                block_device,
+            );
         }
-        // fill indirect2 from (a0, b0) -> (a1, b1)
-        let mut a0 = current_blocks as usize / INODE_INDIRECT1_COUNT;
-        let mut b0 = current_blocks as usize % INODE_INDIRECT1_COUNT;
-        let a1 = total_blocks as usize / INODE_INDIRECT1_COUNT;
-        let b1 = total_blocks as usize % INODE_INDIRECT1_COUNT;
-        // alloc low-level indirect1
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                while (a0 < a1) || (a0 == a1 && b0 < b1) {
-                    if b0 == 0 {
-                        indirect2[a0] = new_blocks.next().unwrap();
-                    }
-                    // fill current
-                    get_block_cache(indirect2[a0] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            indirect1[b0] = new_blocks.next().unwrap();
-                        });
-                    // move to next
-                    b0 += 1;
-                    if b0 == INODE_INDIRECT1_COUNT {
-                        b0 = 0;
-                        a0 += 1;
-                    }
-                }
-            });
+        assert!(new_blocks.next().is_none());
+    }
+    /// Return the number of blocks held, data blocks and index blocks.
+    pub fn allocated_blocks(&self, block_device: &Arc<dyn BlockDevice>) -> u32 {
+        roots_within(0, self.data_blocks() as usize)
+            .map(|(position, levels, from, to)| {
+                count_below(self.root(position), levels, from, to, block_device)
+            })
+            .sum()
     }
 
-    /// Clear size to zero and return blocks that should be deallocated.
+    /// Clear size to zero and pass the blocks that should be deallocated to
+    /// `dealloc`, one index block at a time.
     ///
     /// We will clear the block contents to zero later.
-    pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
-        let mut v: Vec<u32> = Vec::new();
-        let mut data_blocks = self.data_blocks() as usize;
-        self.size = 0;
-        let mut current_blocks = 0usize;
-        // direct
-        while current_blocks < data_blocks.min(INODE_DIRECT_COUNT) {
-            v.push(self.direct[current_blocks]);
-            self.direct[current_blocks] = 0;
-            current_blocks += 1;
+    pub fn clear_size(
+        &mut self,
+        dealloc: &mut impl FnMut(u32),
+        block_device: &Arc<dyn BlockDevice>,
+    ) {
+        let data_blocks = self.data_blocks() as usize;
+        self.set_size(0);
+        for (position, levels, _, _) in roots_within(0, data_blocks) {
+            free_below(self.root(position), levels, dealloc, block_device);
+            *self.root_mut(position) = 0;
         }
-        // indirect1 block
-        if data_blocks > INODE_DIRECT_COUNT {
-            v.push(self.indirect1);
-            data_blocks -= INODE_DIRECT_COUNT;
-            current_blocks = 0;
-        } else {
-            return v;
+    }
+    /// Make holes of all the data blocks from `start` on without freeing
+    /// anything, to cut short an inode damaged there. Only the index blocks
+    /// `is_valid` accepts are followed.
+    pub fn forget_blocks(
+        &mut self,
+        start: u32,
+        is_valid: &impl Fn(u32) -> bool,
+        block_device: &Arc<dyn BlockDevice>,
+    ) {
+        for (position, levels, from, to) in roots_within(start as usize, INDIRECT3_BOUND) {
+            forget_below(
+                self.root_mut(position),
+                levels,
+                from,
+                to,
+                is_valid,
+                block_device,
+            );
         }
-        // indirect1
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-                while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
-                    v.push(indirect1[current_blocks]);
-                    //indirect1[current_blocks] = 0;
-                    current_blocks += 1;
-                }
-            });
-        self.indirect1 = 0;
-        // indirect2 block
-        if data_blocks > INODE_INDIRECT1_COUNT {
-            v.push(self.indirect2);
-            data_blocks -= INODE_INDIRECT1_COUNT;
-        } else {
-            return v;
+    }
+    /// Make holes of the data blocks `start..end` and return the blocks that
+    /// should be deallocated, including index blocks left with only holes
+    /// below them. The size is not changed.
+    pub fn punch_hole(
+        &mut self,
+        start: u32,
+        end: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        let mut v: Vec<u32> = Vec::new();
+        for (position, levels, from, to) in roots_within(start as usize, end as usize) {
+            punch_below(
+                self.root_mut(position),
+                levels,
+                from,
+                to,
+                &mut v,
+                block_device,
+            );
         }
-        // indirect2
-        assert!(data_blocks <= INODE_INDIRECT2_COUNT);
-        let a1 = data_blocks / INODE_INDIRECT1_COUNT;
-        let b1 = data_blocks % INODE_INDIRECT1_COUNT;
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                // full indirect1 blocks
-                for entry in indirect2.iter_mut().take(a1) {
-                    v.push(*entry);
-                    get_block_cache(*entry as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter() {
-                                v.push(*entry);
-                            }
-                        });
-                }
-                // last indirect1 block
-                if b1 > 0 {
-                    v.push(indirect2[a1]);
-                    get_block_cache(indirect2[a1] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter().take(b1) {
-                                v.push(*entry);
-                            }
-                        });
-                    //indirect2[a1] = 0;
-                }
-            });
-        self.indirect2 = 0;
         v
     }
     pub fn read_at(
@@ -298,7 +338,7 @@ impl DiskInode {
         block_device: &Arc<dyn BlockDevice>,
     ) -> usize {
         let mut start = offset;
-        let end = (offset + buf.len()).min(self.size as usize);
+        let end = (offset + buf.len()).min(self.size() as usize);
         if start >= end {
             return 0;
         }
@@ -311,15 +351,18 @@ impl DiskInode {
             // read and update read size
             let block_read_size = end_current_block - start;
             let dst = &mut buf[read_size..read_size + block_read_size];
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .read(0, |data_block: &DataBlock| {
-                let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
-                dst.copy_from_slice(src);
-            });
+            let block_id = self.get_block_id(start_block as u32, block_device);
+            if block_id == 0 {
+                // a hole
+                dst.fill(0);
+            } else {
+                get_block_cache(block_id as usize, Arc::clone(block_device))
+                    .lock()
+                    .read(0, |data_block: &DataBlock| {
+                        let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
+                        dst.copy_from_slice(src);
+                    });
+            }
             read_size += block_read_size;
             // move to next block
             if end_current_block == end {
@@ -330,7 +373,8 @@ impl DiskInode {
         }
         read_size
     }
-    /// File size must be adjusted before.
+    /// File size must be adjusted and the blocks mapped before. Entries of a
+    /// directory are metadata and logged, the content of a file is not.
     pub fn write_at(
         &mut self,
         offset: usize,
@@ -338,7 +382,7 @@ impl DiskInode {
         block_device: &Arc<dyn BlockDevice>,
     ) -> usize {
         let mut start = offset;
-        let end = (offset + buf.len()).min(self.size as usize);
+        let end = (offset + buf.len()).min(self.size() as usize);
         assert!(start <= end);
         let mut start_block = start / BLOCK_SZ;
         let mut write_size = 0usize;
@@ -348,16 +392,21 @@ impl DiskInode {
             end_current_block = end_current_block.min(end);
             // write and update write size
             let block_write_size = end_current_block - start;
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
+            let write_block = |data_block: &mut DataBlock| {
                 let src = &buf[write_size..write_size + block_write_size];
                 let dst = &mut data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_write_size];
                 dst.copy_from_slice(src);
-            });
+            };
+            let block_id = self.get_block_id(start_block as u32, block_device);
+            assert_ne!(block_id, 0, "Writing to a hole!");
+            let block_cache = get_block_cache(block_id as usize, Arc::clone(block_device));
+            let mut block_cache = block_cache.lock();
+            if self.is_dir() {
+                block_cache.modify_logged(0, write_block);
+            } else {
+                block_cache.modify(0, write_block);
+            }
+            drop(block_cache);
             write_size += block_write_size;
             // move to next block
             if end_current_block == end {
@@ -370,40 +419,321 @@ impl DiskInode {
     }
 }
 
-#[repr(C)]
+/// The pointers of an inode which lead to the data blocks `start..end`, each
+/// with its position for `DiskInode::root`, the number of levels of index
+/// blocks from it down, and which of the data blocks below it are in range.
+fn roots_within(start: usize, end: usize) -> impl Iterator<Item = (usize, u32, usize, usize)> {
+    (0..INODE_DIRECT_COUNT)
+        .map(|_| 0)
+        .chain(1..=3)
+        .enumerate()
+        .scan(0, |first, (position, levels)| {
+            let capacity = INODE_INDIRECT1_COUNT.pow(levels);
+            *first += capacity;
+            Some((position, levels, *first - capacity, capacity))
+        })
+        .filter(move |(_, _, first, capacity)| start < first + capacity && *first < end)
+        .map(move |(position, levels, first, capacity)| {
+            (
+                position,
+                levels,
+                start.max(first) - first,
+                end.min(first + capacity) - first,
+            )
+        })
+}
+
+/// The entries of an index block, with `levels` levels of index blocks from
+/// it down, which lead to its data blocks `from..to`, each with which of the
+/// data blocks below the entry are in range.
+fn entries_within(
+    levels: u32,
+    from: usize,
+    to: usize,
+) -> impl Iterator<Item = (usize, usize, usize)> {
+    // each entry covers `span` data blocks
+    let span = INODE_INDIRECT1_COUNT.pow(levels - 1);
+    (from / span..(to - 1) / span + 1).map(move |index| {
+        let start = index * span;
+        (index, from.max(start) - start, to.min(start + span) - start)
+    })
+}
+
+fn read_indirect(block_id: u32, block_device: &Arc<dyn BlockDevice>) -> IndirectBlock {
+    get_block_cache(block_id as usize, Arc::clone(block_device))
+        .lock()
+        .read(0, |indirect_block: &IndirectBlock| *indirect_block)
+}
+
+/// Count the holes among the data blocks `from..to` below `block_id`, which
+/// has `levels` levels of index blocks from it down, and the index blocks
+/// missing on the way. A data block has no levels.
+fn holes_below(
+    block_id: u32,
+    levels: u32,
+    from: usize,
+    to: usize,
+    block_device: &Arc<dyn BlockDevice>,
+) -> u32 {
+    let missing = (block_id == 0) as u32;
+    if levels == 0 {
+        return missing;
+    }
+    let indirect_block = if block_id == 0 {
+        [0; INODE_INDIRECT1_COUNT]
+    } else {
+        read_indirect(block_id, block_device)
+    };
+    missing
+        + entries_within(levels, from, to)
+            .map(|(index, from, to)| {
+                holes_below(indirect_block[index], levels - 1, from, to, block_device)
+            })
+            .sum::<u32>()
+}
+
+/// Fill the holes among the data blocks `from..to` below `*block_id`, as for
+/// `holes_below`, with blocks of `new_blocks`. An index block on the way is
+/// taken before the blocks below it.
+fn fill_holes(
+    block_id: &mut u32,
+    levels: u32,
+    from: usize,
+    to: usize,
+    new_blocks: &mut impl Iterator<Item = u32>,
+    block_device: &Arc<dyn BlockDevice>,
+) {
+    if *block_id == 0 {
+        *block_id = new_blocks.next().unwrap();
+    }
+    if levels == 0 {
+        return;
+    }
+    let indirect_block = read_indirect(*block_id, block_device);
+    for (index, from, to) in entries_within(levels, from, to) {
+        let mut entry = indirect_block[index];
+        fill_holes(&mut entry, levels - 1, from, to, new_blocks, block_device);
+        if entry != indirect_block[index] {
+            get_block_cache(*block_id as usize, Arc::clone(block_device))
+                .lock()
+                .modify_logged(0, |indirect_block: &mut IndirectBlock| {
+                    indirect_block[index] = entry
+                });
+        }
+    }
+}
+
+/// Count the blocks below `block_id` leading to its data blocks `from..to`,
+/// as for `holes_below`, including itself.
+fn count_below(
+    block_id: u32,
+    levels: u32,
+    from: usize,
+    to: usize,
+    block_device: &Arc<dyn BlockDevice>,
+) -> u32 {
+    if block_id == 0 {
+        return 0;
+    }
+    if levels == 0 {
+        return 1;
+    }
+    let indirect_block = read_indirect(block_id, block_device);
+    1 + entries_within(levels, from, to)
+        .map(|(index, from, to)| {
+            count_below(indirect_block[index], levels - 1, from, to, block_device)
+        })
+        .sum::<u32>()
+}
+
+/// Pass the blocks below `block_id`, as for `holes_below`, to `dealloc` and
+/// then itself. Holes are skipped.
+fn free_below(
+    block_id: u32,
+    levels: u32,
+    dealloc: &mut impl FnMut(u32),
+    block_device: &Arc<dyn BlockDevice>,
+) {
+    if block_id == 0 {
+        return;
+    }
+    if levels > 0 {
+        for entry in read_indirect(block_id, block_device) {
+            free_below(entry, levels - 1, dealloc, block_device);
+        }
+    }
+    dealloc(block_id);
+}
+
+/// Make holes of the data blocks from `from` on below `*block_id`, as for
+/// `DiskInode::forget_blocks`, dropping the pointer itself if it leads to
+/// none before `from`.
+fn forget_below(
+    block_id: &mut u32,
+    levels: u32,
+    from: usize,
+    to: usize,
+    is_valid: &impl Fn(u32) -> bool,
+    block_device: &Arc<dyn BlockDevice>,
+) {
+    if from == 0 || !is_valid(*block_id) {
+        *block_id = 0;
+        return;
+    }
+    let mut indirect_block = read_indirect(*block_id, block_device);
+    for (index, from, to) in entries_within(levels, from, to) {
+        forget_below(
+            &mut indirect_block[index],
+            levels - 1,
+            from,
+            to,
+            is_valid,
+            block_device,
+        );
+    }
+    get_block_cache(*block_id as usize, Arc::clone(block_device))
+        .lock()
+        .modify_logged(0, |forgotten: &mut IndirectBlock| {
+            *forgotten = indirect_block
+        });
+}
+
+/// Make holes of the data blocks `from..to` below `*block_id`, as for
+/// `holes_below`, pushing the blocks freed. An index block is freed as well
+/// when only holes are left below it.
+fn punch_below(
+    block_id: &mut u32,
+    levels: u32,
+    from: usize,
+    to: usize,
+    v: &mut Vec<u32>,
+    block_device: &Arc<dyn BlockDevice>,
+) {
+    if *block_id == 0 {
+        return;
+    }
+    if levels > 0 {
+        let mut indirect_block = read_indirect(*block_id, block_device);
+        let old = indirect_block;
+        for (index, from, to) in entries_within(levels, from, to) {
+            punch_below(
+                &mut indirect_block[index],
+                levels - 1,
+                from,
+                to,
+                v,
+                block_device,
+            );
+        }
+        if indirect_block.iter().any(|entry| *entry != 0) {
+            if indirect_block != old {
+                get_block_cache(*block_id as usize, Arc::clone(block_device))
+                    .lock()
+                    .modify_logged(0, |punched: &mut IndirectBlock| *punched = indirect_block);
+            }
+            return;
+        }
+    }
+    v.push(*block_id);
+    *block_id = 0;
+}
+
+/// A directory entry of the size recorded in the SuperBlock: the name padded
+/// with zeros, which end it, followed by the inode number.
 pub struct DirEntry {
-    name: [u8; NAME_LENGTH_LIMIT + 1],
-    inode_number: u32,
+    bytes: Vec<u8>,
 }
 
+/// Size of the entries in images made before it was recorded in the SuperBlock.
 pub const DIRENT_SZ: usize = 32;
 
+/// Return the longest name an entry of `dirent_size` bytes can hold.
+pub fn name_length_limit(dirent_size: usize) -> usize {
+    dirent_size - 4 - 1
+}
+
+/// FNV-1a of a name, which places its entry in a hashed directory.
+pub fn name_hash(name: &str) -> u32 {
+    name.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Largest power of two not above `buckets`.
+fn level_size(buckets: usize) -> usize {
+    1 << (usize::BITS - 1 - buckets.leading_zeros())
+}
+
+/// Return the bucket of a name with `hash` in a hashed directory of `buckets`
+/// blocks, which grows by one bucket at a time through linear hashing:
+/// buckets below `hash_split_bucket(buckets)` have already been split by the
+/// next bit of the hash, the others have not yet.
+///
+/// Each bucket is a block whose first slot is a header, which counts the
+/// entries of the bucket displaced to following buckets as it was full.
+pub fn hash_bucket(hash: u32, buckets: usize) -> usize {
+    let level_size = level_size(buckets);
+    let bucket = hash as usize & (level_size - 1);
+    if bucket < buckets - level_size {
+        hash as usize & (2 * level_size - 1)
+    } else {
+        bucket
+    }
+}
+
+/// Return the bucket split when a hashed directory of `buckets` blocks grows,
+/// its entries are shared with the new bucket `buckets`.
+pub fn hash_split_bucket(buckets: usize) -> usize {
+    buckets - level_size(buckets)
+}
+
 impl DirEntry {
-    pub fn empty() -> Self {
+    pub fn empty(dirent_size: usize) -> Self {
         Self {
-            name: [0u8; NAME_LENGTH_LIMIT + 1],
-            inode_number: 0,
+            bytes: vec![0u8; dirent_size],
         }
     }
-    pub fn new(name: &str, inode_number: u32) -> Self {
-        let mut bytes = [0u8; NAME_LENGTH_LIMIT + 1];
-        bytes[..name.len()].copy_from_slice(name.as_bytes());
+    pub fn new(name: &str, inode_number: u32, dirent_size: usize) -> Self {
+        assert!(name.len() <= name_length_limit(dirent_size));
+        let mut dirent = Self::empty(dirent_size);
+        dirent.bytes[..name.len()].copy_from_slice(name.as_bytes());
+        dirent.bytes[dirent_size - 4..].copy_from_slice(&inode_number.to_ne_bytes());
+        dirent
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Self {
         Self {
-            name: bytes,
-            inode_number,
+            bytes: Vec::from(bytes),
         }
     }
     pub fn as_bytes(&self) -> &[u8] {
-        unsafe { core::slice::from_raw_parts(self as *const _ as usize as *const u8, DIRENT_SZ) }
+        &self.bytes
     }
     pub fn as_bytes_mut(&mut self) -> &mut [u8] {
-        unsafe { core::slice::from_raw_parts_mut(self as *mut _ as usize as *mut u8, DIRENT_SZ) }
+        &mut self.bytes
+    }
+    fn name_bytes(&self) -> &[u8] {
+        &self.bytes[..self.bytes.len() - 4]
     }
     pub fn name(&self) -> &str {
-        let len = (0usize..).find(|i| self.name[*i] == 0).unwrap();
-        core::str::from_utf8(&self.name[..len]).unwrap()
+        let name = self.name_bytes();
+        let len = (0usize..).find(|i| name[*i] == 0).unwrap();
+        core::str::from_utf8(&name[..len]).unwrap()
     }
     pub fn inode_number(&self) -> u32 {
-        self.inode_number
+        let len = self.bytes.len();
+        u32::from_ne_bytes(self.bytes[len - 4..].try_into().unwrap())
+    }
+    /// A slot with an empty name is free and can be reused.
+    pub fn is_empty(&self) -> bool {
+        self.bytes[0] == 0
+    }
+    /// The name is terminated within the limit and valid UTF-8, which only
+    /// a corrupted entry breaks.
+    pub fn is_valid(&self) -> bool {
+        let name = self.name_bytes();
+        match name.iter().position(|byte| *byte == 0) {
+            Some(len) => core::str::from_utf8(&name[..len]).is_ok(),
+            None => false,
+        }
     }
 }
@@ -5,14 +5,28 @@ extern crate alloc;
 mod bitmap;
 mod block_cache;
 mod block_dev;
+mod clock;
 mod efs;
+mod fsck;
+mod journal;
 mod layout;
+mod sync;
 mod vfs;
 
 pub const BLOCK_SZ: usize = 512;
 use bitmap::Bitmap;
-use block_cache::{block_cache_sync_all, get_block_cache};
+pub use block_cache::{block_cache_discard_all, block_cache_sync_all, set_block_cache_capacity};
+use block_cache::{get_block_cache, logged_blocks, BlockCache};
 pub use block_dev::BlockDevice;
+use clock::now;
+pub use clock::set_clock;
 pub use efs::EasyFileSystem;
+use efs::UPDATE_DATA_BLOCKS;
+pub use fsck::Problem;
+use journal::{Journal, JOURNAL_BLOCKS};
 use layout::*;
+pub use layout::{SuperBlock, MAX_FILE_SIZE};
+use sync::RwLock;
+pub use sync::{set_relax, Mutex};
 pub use vfs::Inode;
+use vfs::Inodes;
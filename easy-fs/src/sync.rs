@@ -0,0 +1,64 @@
+use spin::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+
+static RELAX: spin::Mutex<fn()> = spin::Mutex::new(core::hint::spin_loop);
+
+/// Set what is done while waiting for a lock held by somebody else, who may
+/// be waiting for the device in turn. A kernel switching tasks on each
+/// device access should switch to another task here. The default is to spin.
+pub fn set_relax(relax: fn()) {
+    *RELAX.lock() = relax;
+}
+
+fn relax() {
+    let relax = *RELAX.lock();
+    relax();
+}
+
+/// A spin lock relaxing as set by `set_relax` between attempts.
+pub struct Mutex<T: ?Sized>(spin::Mutex<T>);
+
+impl<T> Mutex<T> {
+    pub fn new(value: T) -> Self {
+        Self(spin::Mutex::new(value))
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.0.try_lock() {
+                return guard;
+            }
+            relax();
+        }
+    }
+}
+
+/// A readers-writer spin lock relaxing as set by `set_relax` between attempts.
+pub struct RwLock<T: ?Sized>(spin::RwLock<T>);
+
+impl<T> RwLock<T> {
+    pub fn new(value: T) -> Self {
+        Self(spin::RwLock::new(value))
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.0.try_read() {
+                return guard;
+            }
+            relax();
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.0.try_write() {
+                return guard;
+            }
+            relax();
+        }
+    }
+}
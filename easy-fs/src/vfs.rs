@@ -1,33 +1,101 @@
 use super::{
-    block_cache_sync_all, get_block_cache, BlockDevice, DirEntry, DiskInode, DiskInodeType,
-    EasyFileSystem, DIRENT_SZ,
+    get_block_cache, hash_bucket, hash_split_bucket, name_hash, name_length_limit, now,
+    BlockDevice, DirEntry, DiskInode, DiskInodeType, EasyFileSystem, Mutex, RwLock, BLOCK_SZ,
+    MAX_FILE_SIZE, UPDATE_DATA_BLOCKS,
 };
+use alloc::collections::BTreeMap;
 use alloc::string::String;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
 use alloc::vec::Vec;
-use spin::{Mutex, MutexGuard};
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::{RwLockReadGuard, RwLockWriteGuard};
+
+/// The inodes in use of a filesystem and the locks they share. An update
+/// holds `transaction` shared, then `namespace` if it changes directory
+/// entries, then the locks of the inodes involved. The filesystem itself
+/// comes last and is locked only to reserve room in the journal, to
+/// allocate and to free.
+///
+/// Updates of directory entries lock several inodes in no particular order,
+/// which is safe as they take turns by `namespace` while the others hold the
+/// lock of one inode at a time.
+pub(crate) struct Inodes {
+    /// held exclusively to commit, so that no update is split between transactions
+    transaction: RwLock<()>,
+    namespace: Mutex<()>,
+    /// each inode somebody refers to by inode id, so that there is one `Inode` per inode
+    cache: Mutex<BTreeMap<u32, Weak<Inode>>>,
+    /// orphans dropped, which are reclaimed by the next updates or on sync
+    orphans: Mutex<Vec<u32>>,
+}
+
+impl Inodes {
+    pub(crate) fn new() -> Self {
+        Self {
+            transaction: RwLock::new(()),
+            namespace: Mutex::new(()),
+            cache: Mutex::new(BTreeMap::new()),
+            orphans: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn pop_orphan(&self) -> Option<u32> {
+        self.orphans.lock().pop()
+    }
+}
 
 pub struct Inode {
+    inode_id: u32,
     block_id: usize,
     block_offset: usize,
+    dirent_size: usize,
     fs: Arc<Mutex<EasyFileSystem>>,
+    inodes: Arc<Inodes>,
+    /// held shared to read the inode and exclusively to update it
+    lock: RwLock<()>,
+    /// unlinked while in use, the inode is reclaimed after it is dropped
+    orphan: AtomicBool,
     block_device: Arc<dyn BlockDevice>,
 }
 
 impl Inode {
-    /// We should not acquire efs lock here.
-    pub fn new(
-        block_id: u32,
-        block_offset: usize,
-        fs: Arc<Mutex<EasyFileSystem>>,
-        block_device: Arc<dyn BlockDevice>,
-    ) -> Self {
-        Self {
+    /// Return the inode with the given id, the same `Inode` as long as
+    /// somebody refers to it. The efs lock is taken, so the caller should
+    /// not hold any block.
+    pub(crate) fn get(
+        inode_id: u32,
+        fs: &Arc<Mutex<EasyFileSystem>>,
+        inodes: &Arc<Inodes>,
+    ) -> Arc<Self> {
+        let mut cache = inodes.cache.lock();
+        if let Some(inode) = cache.get(&inode_id).and_then(Weak::upgrade) {
+            return inode;
+        }
+        let (block_id, block_offset, dirent_size, block_device) = {
+            let fs = fs.lock();
+            let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+            (
+                block_id,
+                block_offset,
+                fs.dirent_size(),
+                Arc::clone(&fs.block_device),
+            )
+        };
+        let inode = Arc::new(Self {
+            inode_id,
             block_id: block_id as usize,
             block_offset,
-            fs,
+            dirent_size,
+            fs: Arc::clone(fs),
+            inodes: Arc::clone(inodes),
+            lock: RwLock::new(()),
+            orphan: AtomicBool::new(false),
             block_device,
-        }
+        });
+        cache.retain(|_, inode| inode.strong_count() > 0);
+        cache.insert(inode_id, Arc::downgrade(&inode));
+        inode
     }
 
     fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
@@ -39,148 +107,934 @@ impl Inode {
     fn modify_disk_inode<V>(&self, f: impl FnOnce(&mut DiskInode) -> V) -> V {
         get_block_cache(self.block_id, Arc::clone(&self.block_device))
             .lock()
-            .modify(self.block_offset, f)
+            .modify_logged(self.block_offset, f)
     }
 
-    fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
+    /// Start an update, which is a whole in the journal. The transaction is
+    /// committed first if it has no room left, once the running updates are over.
+    /// An orphan dropped before is reclaimed along with the update.
+    fn start_update(&self) -> RwLockReadGuard<'_, ()> {
+        loop {
+            let update = self.inodes.transaction.read();
+            if self.fs.lock().reserve_update() {
+                if let Some(inode_id) = self.inodes.pop_orphan() {
+                    self.get_inode(inode_id).free();
+                }
+                return update;
+            }
+            drop(update);
+            let _commit = self.inodes.transaction.write();
+            self.fs.lock().commit_if_full();
+        }
+    }
+
+    /// Lock the distinct ones of `inodes` to update directory entries, the
+    /// caller should hold `namespace`.
+    fn lock_inodes<'a>(inodes: &[&'a Inode]) -> Vec<RwLockWriteGuard<'a, ()>> {
+        let mut inode_ids = Vec::new();
+        let mut guards = Vec::new();
+        for inode in inodes {
+            if !inode_ids.contains(&inode.inode_id) {
+                inode_ids.push(inode.inode_id);
+                guards.push(inode.lock.write());
+            }
+        }
+        guards
+    }
+
+    fn get_inode(&self, inode_id: u32) -> Arc<Inode> {
+        Self::get(inode_id, &self.fs, &self.inodes)
+    }
+
+    fn read_dirent(&self, slot: usize, disk_inode: &DiskInode) -> DirEntry {
+        let mut dirent = DirEntry::empty(self.dirent_size);
+        assert_eq!(
+            disk_inode.read_at(
+                slot * self.dirent_size,
+                dirent.as_bytes_mut(),
+                &self.block_device,
+            ),
+            self.dirent_size,
+        );
+        dirent
+    }
+
+    /// Return (slot, inode_id) of the entry named `name`.
+    fn find_dirent(&self, name: &str, disk_inode: &DiskInode) -> Option<(usize, u32)> {
         // assert it is a directory
         assert!(disk_inode.is_dir());
-        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-        let mut dirent = DirEntry::empty();
-        for i in 0..file_count {
-            assert_eq!(
-                disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device,),
-                DIRENT_SZ,
-            );
-            if dirent.name() == name {
-                return Some(dirent.inode_number() as u32);
+        if disk_inode.is_hashed() {
+            return self.find_hashed(name, disk_inode);
+        }
+        let file_count = (disk_inode.size() as usize) / self.dirent_size;
+        (0..file_count).find_map(|slot| {
+            let dirent = self.read_dirent(slot, disk_inode);
+            if !dirent.is_empty() && dirent.name() == name {
+                Some((slot, dirent.inode_number()))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
+        self.find_dirent(name, disk_inode)
+            .map(|(_, inode_id)| inode_id)
+    }
+
+    /// Put a new entry into the first free slot, the directory grows if there is none.
+    fn insert_dirent(&self, name: &str, inode_id: u32, disk_inode: &mut DiskInode) {
+        if disk_inode.is_hashed() {
+            self.insert_hashed(name, inode_id, disk_inode);
+            return;
+        }
+        let file_count = (disk_inode.size() as usize) / self.dirent_size;
+        let slot = (0..file_count)
+            .find(|slot| self.read_dirent(*slot, disk_inode).is_empty())
+            .unwrap_or_else(|| {
+                self.increase_size(((file_count + 1) * self.dirent_size) as u64, disk_inode);
+                file_count
+            });
+        self.write_dirent(slot, name, inode_id, disk_inode);
+    }
+
+    fn write_dirent(&self, slot: usize, name: &str, inode_id: u32, disk_inode: &mut DiskInode) {
+        let dirent = DirEntry::new(name, inode_id, self.dirent_size);
+        disk_inode.write_at(
+            slot * self.dirent_size,
+            dirent.as_bytes(),
+            &self.block_device,
+        );
+        disk_inode.touch_modified(now());
+    }
+
+    /// Free the slot, the size of the directory does not shrink.
+    fn remove_dirent(&self, slot: usize, disk_inode: &mut DiskInode) {
+        if disk_inode.is_hashed() {
+            let name = String::from(self.read_dirent(slot, disk_inode).name());
+            let home = hash_bucket(name_hash(&name), Self::buckets(disk_inode));
+            if slot / self.slots_per_bucket() != home {
+                let displaced = self.read_displaced(home, disk_inode);
+                self.write_displaced(home, displaced.saturating_sub(1), disk_inode);
+            }
+        }
+        self.clear_slot(slot, disk_inode);
+    }
+
+    fn clear_slot(&self, slot: usize, disk_inode: &mut DiskInode) {
+        let dirent = DirEntry::empty(self.dirent_size);
+        disk_inode.write_at(
+            slot * self.dirent_size,
+            dirent.as_bytes(),
+            &self.block_device,
+        );
+        disk_inode.touch_modified(now());
+    }
+
+    /// Number of buckets of a hashed directory, each one a block.
+    fn buckets(disk_inode: &DiskInode) -> usize {
+        disk_inode.size() as usize / BLOCK_SZ
+    }
+
+    /// Number of slots in a bucket, including the header.
+    fn slots_per_bucket(&self) -> usize {
+        BLOCK_SZ / self.dirent_size
+    }
+
+    /// Buckets from `bucket` on, wrapping around to the first one.
+    fn buckets_from(bucket: usize, buckets: usize) -> impl Iterator<Item = usize> {
+        (bucket..buckets).chain(0..bucket)
+    }
+
+    /// Read the slots of a bucket, the header first.
+    fn read_bucket(&self, bucket: usize, disk_inode: &DiskInode) -> Vec<DirEntry> {
+        let mut block = [0u8; BLOCK_SZ];
+        disk_inode.read_at(bucket * BLOCK_SZ, &mut block, &self.block_device);
+        block
+            .chunks(self.dirent_size)
+            .map(DirEntry::from_bytes)
+            .collect()
+    }
+
+    /// Return the number of entries displaced from `bucket`, which is kept as
+    /// the inode number of its header.
+    fn read_displaced(&self, bucket: usize, disk_inode: &DiskInode) -> u32 {
+        self.read_dirent(bucket * self.slots_per_bucket(), disk_inode)
+            .inode_number()
+    }
+
+    fn write_displaced(&self, bucket: usize, displaced: u32, disk_inode: &mut DiskInode) {
+        let header = DirEntry::new("", displaced, self.dirent_size);
+        disk_inode.write_at(bucket * BLOCK_SZ, header.as_bytes(), &self.block_device);
+    }
+
+    /// Return (slot, inode_id) of `name` in a hashed directory. If it is not in
+    /// its bucket, the following buckets are searched until all the entries
+    /// displaced from there have been seen.
+    fn find_hashed(&self, name: &str, disk_inode: &DiskInode) -> Option<(usize, u32)> {
+        let buckets = Self::buckets(disk_inode);
+        if buckets == 0 {
+            return None;
+        }
+        let home = hash_bucket(name_hash(name), buckets);
+        let mut displaced = self.read_displaced(home, disk_inode);
+        for bucket in Self::buckets_from(home, buckets) {
+            for (i, dirent) in self
+                .read_bucket(bucket, disk_inode)
+                .iter()
+                .enumerate()
+                .skip(1)
+            {
+                if dirent.is_empty() {
+                    continue;
+                }
+                if dirent.name() == name {
+                    return Some((bucket * self.slots_per_bucket() + i, dirent.inode_number()));
+                }
+                if bucket != home && hash_bucket(name_hash(dirent.name()), buckets) == home {
+                    displaced = displaced.saturating_sub(1);
+                }
+            }
+            if displaced == 0 {
+                break;
             }
         }
         None
     }
 
-    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
-        let fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            self.find_inode_id(name, disk_inode).map(|inode_id| {
-                let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
-                Arc::new(Self::new(
-                    block_id,
-                    block_offset,
-                    self.fs.clone(),
-                    self.block_device.clone(),
-                ))
-            })
+    /// Put a new entry into its bucket of a hashed directory. A full bucket
+    /// has the directory grow by a bucket first, which makes room in it if it
+    /// is the one split, otherwise the entry is displaced.
+    fn insert_hashed(&self, name: &str, inode_id: u32, disk_inode: &mut DiskInode) {
+        let buckets = Self::buckets(disk_inode);
+        if buckets == 0
+            || !self
+                .read_bucket(hash_bucket(name_hash(name), buckets), disk_inode)
+                .iter()
+                .skip(1)
+                .any(DirEntry::is_empty)
+        {
+            self.split_bucket(disk_inode);
+        }
+        // all buckets are full
+        while !self.place_hashed(name, inode_id, disk_inode) {
+            self.split_bucket(disk_inode);
+        }
+    }
+
+    /// Write an entry into the first free slot from its bucket on, counting it
+    /// in the header of its bucket if it is displaced to a following one.
+    /// Return false if there is no free slot.
+    fn place_hashed(&self, name: &str, inode_id: u32, disk_inode: &mut DiskInode) -> bool {
+        let buckets = Self::buckets(disk_inode);
+        let home = hash_bucket(name_hash(name), buckets);
+        let free = Self::buckets_from(home, buckets).find_map(|bucket| {
+            self.read_bucket(bucket, disk_inode)
+                .iter()
+                .skip(1)
+                .position(DirEntry::is_empty)
+                .map(|i| (bucket, bucket * self.slots_per_bucket() + 1 + i))
+        });
+        let (bucket, slot) = match free {
+            Some(free) => free,
+            None => return false,
+        };
+        if bucket != home {
+            let displaced = self.read_displaced(home, disk_inode);
+            self.write_displaced(home, displaced + 1, disk_inode);
+        }
+        self.write_dirent(slot, name, inode_id, disk_inode);
+        true
+    }
+
+    /// Grow a hashed directory by a bucket. The entries of the bucket split,
+    /// wherever they are, are placed again by one more bit of their hash,
+    /// which sends some of them to the new bucket.
+    fn split_bucket(&self, disk_inode: &mut DiskInode) {
+        let buckets = Self::buckets(disk_inode);
+        let mut moved = Vec::new();
+        if buckets > 0 {
+            let split = hash_split_bucket(buckets);
+            let mut displaced = self.read_displaced(split, disk_inode);
+            for bucket in Self::buckets_from(split, buckets) {
+                for (i, dirent) in self
+                    .read_bucket(bucket, disk_inode)
+                    .iter()
+                    .enumerate()
+                    .skip(1)
+                {
+                    if dirent.is_empty() || hash_bucket(name_hash(dirent.name()), buckets) != split
+                    {
+                        continue;
+                    }
+                    self.clear_slot(bucket * self.slots_per_bucket() + i, disk_inode);
+                    moved.push((String::from(dirent.name()), dirent.inode_number()));
+                    if bucket != split {
+                        displaced = displaced.saturating_sub(1);
+                    }
+                }
+                if displaced == 0 {
+                    break;
+                }
+            }
+            self.write_displaced(split, 0, disk_inode);
+        }
+        self.increase_size(((buckets + 1) * BLOCK_SZ) as u64, disk_inode);
+        for (name, inode_id) in moved {
+            assert!(self.place_hashed(&name, inode_id, disk_inode));
+        }
+    }
+
+    /// A directory is empty if it contains nothing but "." and "..".
+    fn is_empty_dir(&self, disk_inode: &DiskInode) -> bool {
+        let file_count = (disk_inode.size() as usize) / self.dirent_size;
+        (0..file_count).all(|slot| {
+            let dirent = self.read_dirent(slot, disk_inode);
+            dirent.is_empty() || dirent.name() == "." || dirent.name() == ".."
         })
     }
 
-    fn increase_size(
-        &self,
-        new_size: u32,
-        disk_inode: &mut DiskInode,
-        fs: &mut MutexGuard<EasyFileSystem>,
-    ) {
-        if new_size < disk_inode.size {
+    /// Return the longest name an entry in this filesystem can hold.
+    pub fn name_length_limit(&self) -> usize {
+        name_length_limit(self.dirent_size)
+    }
+
+    pub fn inode_id(&self) -> u32 {
+        self.inode_id
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.read_disk_inode(|disk_inode| disk_inode.is_file())
+    }
+
+    pub fn size(&self) -> u64 {
+        let _inode = self.lock.read();
+        self.read_disk_inode(|disk_inode| disk_inode.size())
+    }
+
+    pub fn nlink(&self) -> u32 {
+        let _inode = self.lock.read();
+        self.read_disk_inode(|disk_inode| disk_inode.nlink)
+    }
+
+    /// Number of blocks held by the inode, including index blocks.
+    pub fn blocks(&self) -> u64 {
+        let _inode = self.lock.read();
+        self.read_disk_inode(|disk_inode| disk_inode.allocated_blocks(&self.block_device) as u64)
+    }
+
+    /// Permission bits of the inode.
+    pub fn mode(&self) -> u16 {
+        let _inode = self.lock.read();
+        self.read_disk_inode(|disk_inode| disk_inode.mode)
+    }
+
+    pub fn set_mode(&self, mode: u16) {
+        let _update = self.start_update();
+        let _inode = self.lock.write();
+        self.modify_disk_inode(|disk_inode| {
+            disk_inode.mode = mode & 0o7777;
+            disk_inode.ctime = now();
+        });
+    }
+
+    /// Return (atime, mtime, ctime) in milliseconds.
+    pub fn times(&self) -> (u64, u64, u64) {
+        let _inode = self.lock.read();
+        self.read_disk_inode(|disk_inode| (disk_inode.atime, disk_inode.mtime, disk_inode.ctime))
+    }
+
+    /// Set the access and modification time, the change time becomes now.
+    pub fn set_times(&self, atime: u64, mtime: u64) {
+        let _update = self.start_update();
+        let _inode = self.lock.write();
+        self.modify_disk_inode(|disk_inode| {
+            disk_inode.atime = atime;
+            disk_inode.mtime = mtime;
+            disk_inode.ctime = now();
+        });
+    }
+
+    /// Look up `name` in this directory, return `None` if this is not a directory.
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        let inode_id = {
+            let _inode = self.lock.read();
+            self.read_disk_inode(|disk_inode| {
+                if !disk_inode.is_dir() {
+                    return None;
+                }
+                self.find_inode_id(name, disk_inode)
+            })?
+        };
+        Some(self.get_inode(inode_id))
+    }
+
+    fn increase_size(&self, new_size: u64, disk_inode: &mut DiskInode) {
+        let size = disk_inode.size();
+        if new_size < size {
             return;
         }
-        let blocks_needed = disk_inode.blocks_num_needed(new_size);
-        let mut v: Vec<u32> = Vec::new();
-        for _ in 0..blocks_needed {
-            v.push(fs.alloc_data());
-        }
-        disk_inode.increase_size(new_size, v, &self.block_device);
+        disk_inode.set_size(new_size);
+        self.map_range(size, new_size, disk_inode);
     }
 
-    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
-        let mut fs = self.fs.lock();
-        let op = |root_inode: &mut DiskInode| {
-            // assert it is a directory
-            assert!(root_inode.is_dir());
-            // has the file been created?
-            self.find_inode_id(name, root_inode)
+    /// Allocate the blocks holding the bytes `start..end` which are holes.
+    fn map_range(&self, start: u64, end: u64, disk_inode: &mut DiskInode) {
+        if start >= end {
+            return;
+        }
+        let start_block = (start / BLOCK_SZ as u64) as u32;
+        let end_block = DiskInode::total_data_blocks(end);
+        let blocks_needed = disk_inode.blocks_to_map(start_block, end_block, &self.block_device);
+        let v: Vec<u32> = {
+            let mut fs = self.fs.lock();
+            (0..blocks_needed).map(|_| fs.alloc_data()).collect()
         };
-        if self.modify_disk_inode(op).is_some() {
+        disk_inode.map_blocks(start_block, end_block, v, &self.block_device);
+    }
+
+    /// Return all data blocks of the inode to the allocator.
+    fn release_data(&self, disk_inode: &mut DiskInode) {
+        disk_inode.clear_size(
+            &mut |block_id| self.fs.lock().dealloc_data(block_id),
+            &self.block_device,
+        );
+    }
+
+    fn create_inode(&self, name: &str, type_: DiskInodeType) -> Option<Arc<Inode>> {
+        if name.len() > self.name_length_limit() {
             return None;
         }
-        // create a new file
-        // alloc a inode with an indirect block
-        let new_inode_id = fs.alloc_inode();
+        let _update = self.start_update();
+        let _namespace = self.inodes.namespace.lock();
+        let _dir = self.lock.write();
+        let op = |dir_inode: &DiskInode| {
+            // assert it is a directory, which has not been removed
+            if !dir_inode.is_dir() || dir_inode.nlink == 0 {
+                return None;
+            }
+            // has the file been created?
+            match self.find_inode_id(name, dir_inode) {
+                Some(_) => None,
+                None => Some(()),
+            }
+        };
+        self.read_disk_inode(op)?;
+        // create a new inode
+        let (new_inode_id, hashed) = {
+            let mut fs = self.fs.lock();
+            (fs.alloc_inode(), fs.hashed_dirs())
+        };
+        let new_inode = self.get_inode(new_inode_id);
         // initialize inode
-        let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
-            .lock()
-            .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(DiskInodeType::File);
-            });
-        self.modify_disk_inode(|root_inode| {
-            // append file in the dirent
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            // increase size
-            self.increase_size(new_size as u32, root_inode, &mut fs);
+        new_inode.modify_disk_inode(|disk_inode| {
+            disk_inode.initialize(type_, now());
+            if disk_inode.is_dir() {
+                if hashed {
+                    disk_inode.set_hashed();
+                }
+                self.insert_dirent(".", new_inode_id, disk_inode);
+                self.insert_dirent("..", self.inode_id, disk_inode);
+            }
+        });
+        self.modify_disk_inode(|dir_inode| {
             // write dirent
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
+            self.insert_dirent(name, new_inode_id, dir_inode);
+            // ".." of the new directory refers to this one
+            if type_ == DiskInodeType::Directory {
+                dir_inode.nlink += 1;
+            }
         });
+        Some(new_inode)
+    }
 
-        let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        block_cache_sync_all();
-        // return inode
-        Some(Arc::new(Self::new(
-            block_id,
-            block_offset,
-            self.fs.clone(),
-            self.block_device.clone(),
-        )))
-        // release efs lock automatically by compiler
+    /// Create a regular file in this directory.
+    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::File)
+    }
+
+    /// Create a sub-directory containing "." and "..".
+    pub fn mkdir(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::Directory)
+    }
+
+    /// Remove an empty sub-directory, its inode is reclaimed once nobody
+    /// refers to it.
+    pub fn rmdir(&self, name: &str) -> bool {
+        if name == "." || name == ".." {
+            return false;
+        }
+        let _update = self.start_update();
+        let _namespace = self.inodes.namespace.lock();
+        let _parent = self.lock.write();
+        let (slot, inode_id) = match self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_dir() {
+                return None;
+            }
+            self.find_dirent(name, disk_inode)
+        }) {
+            Some(pair) => pair,
+            None => return false,
+        };
+        let dir = self.get_inode(inode_id);
+        let _dir = dir.lock.write();
+        if !dir.read_disk_inode(|disk_inode| disk_inode.is_dir() && self.is_empty_dir(disk_inode))
+        {
+            return false;
+        }
+        self.modify_disk_inode(|disk_inode| {
+            self.remove_dirent(slot, disk_inode);
+            disk_inode.nlink -= 1;
+        });
+        dir.remove_dir();
+        true
+    }
+
+    /// Leave an empty directory removed from its parent, its entries go at
+    /// once and the inode as for an unlinked file. It takes no new entries.
+    fn remove_dir(self: &Arc<Self>) {
+        self.modify_disk_inode(|disk_inode| {
+            self.release_data(disk_inode);
+            disk_inode.nlink = 0;
+            disk_inode.ctime = now();
+        });
+        self.release();
+    }
+
+    /// Reclaim an inode whose last link is gone, at once if nobody else
+    /// refers to it, otherwise after the last one drops it.
+    fn release(self: &Arc<Self>) {
+        if Arc::strong_count(self) == 1 {
+            self.free();
+        } else {
+            self.orphan.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Add a new entry `name` in this directory referring to `inode`,
+    /// hard links to directories are not allowed.
+    pub fn link(&self, name: &str, inode: &Inode) -> bool {
+        if name.len() > self.name_length_limit() {
+            return false;
+        }
+        let _update = self.start_update();
+        let _namespace = self.inodes.namespace.lock();
+        let _inodes = Self::lock_inodes(&[self, inode]);
+        // an unlinked file is not brought back
+        if inode.read_disk_inode(|disk_inode| disk_inode.is_dir() || disk_inode.nlink == 0) {
+            return false;
+        }
+        let exists = self.read_disk_inode(|disk_inode| {
+            !disk_inode.is_dir()
+                || disk_inode.nlink == 0
+                || self.find_inode_id(name, disk_inode).is_some()
+        });
+        if exists {
+            return false;
+        }
+        self.modify_disk_inode(|disk_inode| {
+            self.insert_dirent(name, inode.inode_id, disk_inode);
+        });
+        inode.modify_disk_inode(|disk_inode| {
+            disk_inode.nlink += 1;
+            disk_inode.ctime = now();
+        });
+        true
+    }
+
+    /// Remove the entry `name` of a non-directory from this directory,
+    /// the inode and its data blocks are reclaimed once the last link is gone
+    /// and nobody refers to the inode any more.
+    pub fn unlink(&self, name: &str) -> bool {
+        let _update = self.start_update();
+        let _namespace = self.inodes.namespace.lock();
+        let _dir = self.lock.write();
+        let (slot, inode_id) = match self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_dir() {
+                return None;
+            }
+            self.find_dirent(name, disk_inode)
+        }) {
+            Some(pair) => pair,
+            None => return false,
+        };
+        let inode = self.get_inode(inode_id);
+        let _inode = inode.lock.write();
+        if inode.read_disk_inode(|disk_inode| disk_inode.is_dir()) {
+            return false;
+        }
+        self.modify_disk_inode(|disk_inode| self.remove_dirent(slot, disk_inode));
+        let nlink = inode.modify_disk_inode(|disk_inode| {
+            disk_inode.nlink -= 1;
+            disk_inode.ctime = now();
+            disk_inode.nlink
+        });
+        if nlink == 0 {
+            inode.release();
+        }
+        true
+    }
+
+    /// Move the entry `old_name` of this directory to `new_name` in `new_dir`.
+    ///
+    /// An existing `new_name` is replaced by rewriting its entry in place, so it never
+    /// disappears in between. It must be an empty directory if a directory is moved,
+    /// otherwise it must not be a directory.
+    pub fn rename(&self, old_name: &str, new_dir: &Inode, new_name: &str) -> bool {
+        if [old_name, new_name].iter().any(|name| *name == "." || *name == "..") {
+            return false;
+        }
+        if new_name.len() > self.name_length_limit() {
+            return false;
+        }
+        let _update = self.start_update();
+        let _namespace = self.inodes.namespace.lock();
+        let _dirs = Self::lock_inodes(&[self, new_dir]);
+        let (old_slot, inode_id) = match self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_dir() {
+                return None;
+            }
+            self.find_dirent(old_name, disk_inode)
+        }) {
+            Some(pair) => pair,
+            None => return false,
+        };
+        if !new_dir.read_disk_inode(|disk_inode| disk_inode.is_dir() && disk_inode.nlink > 0) {
+            return false;
+        }
+        let inode = self.get_inode(inode_id);
+        let is_dir = inode.read_disk_inode(|disk_inode| disk_inode.is_dir());
+        let same_dir = self.inode_id == new_dir.inode_id;
+        // a directory cannot be moved into itself or its descendants
+        if is_dir && !same_dir {
+            let mut ancestor_id = new_dir.inode_id;
+            loop {
+                if ancestor_id == inode_id {
+                    return false;
+                }
+                if ancestor_id == 0 {
+                    break;
+                }
+                ancestor_id = self
+                    .get_inode(ancestor_id)
+                    .read_disk_inode(|disk_inode| self.find_inode_id("..", disk_inode).unwrap());
+            }
+        }
+        let target = new_dir.read_disk_inode(|disk_inode| self.find_dirent(new_name, disk_inode));
+        if matches!(target, Some((_, target_id)) if target_id == inode_id) {
+            return true;
+        }
+        // this directory is already locked, and it is not empty to be replaced
+        if matches!(target, Some((_, target_id)) if target_id == self.inode_id) {
+            return false;
+        }
+        let target = target.map(|(new_slot, target_id)| (new_slot, self.get_inode(target_id)));
+        let _inodes = match &target {
+            Some((_, target)) => Self::lock_inodes(&[&inode, target]),
+            None => Self::lock_inodes(&[&inode]),
+        };
+        let replaced = match &target {
+            Some((new_slot, target)) => {
+                let valid = target.read_disk_inode(|disk_inode| {
+                    if is_dir {
+                        disk_inode.is_dir() && self.is_empty_dir(disk_inode)
+                    } else {
+                        !disk_inode.is_dir()
+                    }
+                });
+                if !valid {
+                    return false;
+                }
+                new_dir.modify_disk_inode(|disk_inode| {
+                    self.write_dirent(*new_slot, new_name, inode_id, disk_inode);
+                });
+                Some(target)
+            }
+            None => {
+                new_dir.modify_disk_inode(|disk_inode| {
+                    self.insert_dirent(new_name, inode_id, disk_inode);
+                });
+                None
+            }
+        };
+        self.modify_disk_inode(|disk_inode| self.remove_dirent(old_slot, disk_inode));
+        // drop the link held by the replaced entry
+        if let Some(target) = replaced {
+            if is_dir {
+                new_dir.modify_disk_inode(|disk_inode| disk_inode.nlink -= 1);
+                target.remove_dir();
+            } else {
+                let nlink = target.modify_disk_inode(|disk_inode| {
+                    disk_inode.nlink -= 1;
+                    disk_inode.ctime = now();
+                    disk_inode.nlink
+                });
+                if nlink == 0 {
+                    target.release();
+                }
+            }
+        }
+        // ".." of a moved directory follows it to the new parent
+        if is_dir && !same_dir {
+            inode.modify_disk_inode(|disk_inode| {
+                let (slot, _) = self.find_dirent("..", disk_inode).unwrap();
+                self.write_dirent(slot, "..", new_dir.inode_id, disk_inode);
+            });
+            self.modify_disk_inode(|disk_inode| disk_inode.nlink -= 1);
+            new_dir.modify_disk_inode(|disk_inode| disk_inode.nlink += 1);
+        }
+        inode.modify_disk_inode(|disk_inode| disk_inode.ctime = now());
+        true
+    }
+
+    /// Reclaim the data blocks and the inode itself.
+    fn free(&self) {
+        self.modify_disk_inode(|disk_inode| self.release_data(disk_inode));
+        self.fs.lock().dealloc_inode(self.inode_id);
     }
 
+    /// Initialize "." and ".." of a fresh directory, used when formatting the root.
+    pub(crate) fn initialize_dir(&self, parent_inode_id: u32) {
+        let _update = self.start_update();
+        let _dir = self.lock.write();
+        self.modify_disk_inode(|disk_inode| {
+            assert!(disk_inode.is_dir());
+            self.insert_dirent(".", self.inode_id, disk_inode);
+            self.insert_dirent("..", parent_inode_id, disk_inode);
+        });
+    }
+
+    /// List names in this directory except "." and "..".
     pub fn ls(&self) -> Vec<String> {
-        let _fs = self.fs.lock();
+        let _inode = self.lock.read();
         self.read_disk_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+            let file_count = (disk_inode.size() as usize) / self.dirent_size;
             let mut v: Vec<String> = Vec::new();
             for i in 0..file_count {
-                let mut dirent = DirEntry::empty();
-                assert_eq!(
-                    disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device,),
-                    DIRENT_SZ,
-                );
+                let dirent = self.read_dirent(i, disk_inode);
+                if dirent.is_empty() || dirent.name() == "." || dirent.name() == ".." {
+                    continue;
+                }
                 v.push(String::from(dirent.name()));
             }
             v
         })
     }
 
+    /// Return the first entry at or after `slot` with the slot following it,
+    /// `None` if there are no more entries or this is not a directory.
+    ///
+    /// Entries of a hashed directory move when it grows, so one created
+    /// between calls may have others listed twice or skipped.
+    pub fn read_dir(&self, slot: usize) -> Option<(usize, String, Arc<Inode>)> {
+        let (next, name, inode_id) = {
+            let _inode = self.lock.read();
+            self.read_disk_inode(|disk_inode| {
+                if !disk_inode.is_dir() {
+                    return None;
+                }
+                let file_count = (disk_inode.size() as usize) / self.dirent_size;
+                (slot..file_count).find_map(|slot| {
+                    let dirent = self.read_dirent(slot, disk_inode);
+                    if dirent.is_empty() {
+                        return None;
+                    }
+                    Some((slot + 1, String::from(dirent.name()), dirent.inode_number()))
+                })
+            })?
+        };
+        Some((next, name, self.get_inode(inode_id)))
+    }
+
+    /// Read at `offset` into `buf`, the time of access is stored only as
+    /// `DiskInode::needs_atime` says.
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-        let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))
+        let (read_size, needs_atime) = {
+            let _inode = self.lock.read();
+            // a copy, so that the block of the inode is not held during the read
+            let disk_inode = self.read_disk_inode(DiskInode::clone);
+            (
+                disk_inode.read_at(offset, buf, &self.block_device),
+                disk_inode.needs_atime(now()),
+            )
+        };
+        if needs_atime {
+            let _update = self.start_update();
+            let _inode = self.lock.write();
+            self.modify_disk_inode(|disk_inode| disk_inode.atime = now());
+        }
+        read_size
     }
 
+    /// Write `buf` at `offset`, the file grows to cover it and what is skipped
+    /// beyond its end is left a hole. Nothing is written beyond `MAX_FILE_SIZE`.
+    /// A large write is split into several updates, see `update_ranges`.
     pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let end = (offset.saturating_add(buf.len()) as u64).min(MAX_FILE_SIZE);
+        if offset as u64 >= end {
+            return 0;
+        }
+        for (start, stop) in update_ranges(offset as u64, end) {
+            let buf = &buf[(start - offset as u64) as usize..(stop - offset as u64) as usize];
+            let _update = self.start_update();
+            let _inode = self.lock.write();
+            self.modify_disk_inode(|disk_inode| {
+                self.map_range(start, stop, disk_inode);
+                disk_inode.set_size(stop.max(disk_inode.size()));
+                disk_inode.touch_modified(now());
+                disk_inode.write_at(start as usize, buf, &self.block_device)
+            });
+        }
+        (end - offset as u64) as usize
+    }
+
+    /// Allocate the blocks holding `offset..offset + len` which are holes, the
+    /// file grows to cover them. Return false for a directory, or if the range
+    /// is empty or goes beyond `MAX_FILE_SIZE`.
+    pub fn allocate(&self, offset: usize, len: usize) -> bool {
+        let end = offset as u64 + len as u64;
+        if len == 0 || end > MAX_FILE_SIZE {
+            return false;
+        }
+        if self.is_dir() {
+            return false;
+        }
+        for (start, stop) in update_ranges(offset as u64, end) {
+            let _update = self.start_update();
+            let _inode = self.lock.write();
+            self.modify_disk_inode(|disk_inode| {
+                self.map_range(start, stop, disk_inode);
+                if stop > disk_inode.size() {
+                    disk_inode.set_size(stop);
+                    disk_inode.touch_modified(now());
+                }
+            });
+        }
+        true
+    }
+
+    /// Free the blocks wholly within `offset..offset + len` and zero the rest
+    /// of the range, which then reads as zeros. The size is kept, and a block
+    /// only cut by the end of the file is freed as well. Return false for a
+    /// directory.
+    pub fn punch_hole(&self, offset: usize, len: usize) -> bool {
+        let _update = self.start_update();
+        let _inode = self.lock.write();
+        self.modify_disk_inode(|disk_inode| {
+            if disk_inode.is_dir() {
+                return false;
+            }
+            let start = offset as u64;
+            let end = start.saturating_add(len as u64).min(disk_inode.size());
+            if start < end {
+                self.punch_range(start, end, disk_inode);
+                disk_inode.touch_modified(now());
+            }
+            true
+        })
+    }
+
+    /// Set the size of a file, which grows by a hole or gives back the blocks
+    /// cut off. Return false for a directory.
+    pub fn set_size(&self, size: u64) -> bool {
+        let _update = self.start_update();
+        let _inode = self.lock.write();
+        self.modify_disk_inode(|disk_inode| {
+            if disk_inode.is_dir() {
+                return false;
+            }
+            let old_size = disk_inode.size();
+            if size < old_size {
+                self.punch_range(size, old_size, disk_inode);
+            }
+            disk_inode.set_size(size);
+            disk_inode.touch_modified(now());
+            true
+        })
+    }
+
+    /// Make a hole of the bytes `start..end`, which are within the file, as
+    /// `punch_hole` does.
+    fn punch_range(&self, start: u64, end: u64, disk_inode: &mut DiskInode) {
+        let first_block = DiskInode::total_data_blocks(start);
+        let end_block = if end == disk_inode.size() {
+            disk_inode.data_blocks()
+        } else {
+            (end / BLOCK_SZ as u64) as u32
+        };
+        // zero what is left of the blocks at either end, unless they are holes
+        let mut zero = |start: u64, end: u64| {
+            if start < end
+                && disk_inode.get_block_id((start / BLOCK_SZ as u64) as u32, &self.block_device)
+                    != 0
+            {
+                let zeros = vec![0u8; (end - start) as usize];
+                disk_inode.write_at(start as usize, &zeros, &self.block_device);
+            }
+        };
+        if first_block < end_block {
+            zero(start, first_block as u64 * BLOCK_SZ as u64);
+            zero(end_block as u64 * BLOCK_SZ as u64, end);
+        } else {
+            zero(start, end);
+        }
+        let freed = disk_inode.punch_hole(first_block, end_block, &self.block_device);
         let mut fs = self.fs.lock();
-        let size = self.modify_disk_inode(|disk_inode| {
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
-            disk_inode.write_at(offset, buf, &self.block_device)
-        });
-        block_cache_sync_all();
-        size
+        for block_id in freed {
+            fs.dealloc_data(block_id);
+        }
+    }
+
+    /// Reclaim the orphans dropped and write everything back, which covers
+    /// this inode. The block cache is shared by all inodes.
+    pub fn sync(&self) {
+        let _commit = self.inodes.transaction.write();
+        while let Some(inode_id) = self.inodes.pop_orphan() {
+            while !self.fs.lock().reserve_update() {
+                self.fs.lock().sync();
+            }
+            self.get_inode(inode_id).free();
+        }
+        self.fs.lock().sync();
     }
 
     pub fn clear(&self) {
-        let mut fs = self.fs.lock();
+        let _update = self.start_update();
+        let _inode = self.lock.write();
         self.modify_disk_inode(|disk_inode| {
-            let size = disk_inode.size;
-            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
-            assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
-            for data_block in data_blocks_dealloc.into_iter() {
-                fs.dealloc_data(data_block);
-            }
+            self.release_data(disk_inode);
+            disk_inode.touch_modified(now());
         });
-        block_cache_sync_all();
+    }
+}
+
+/// Split the bytes `start..end` at every `UPDATE_DATA_BLOCKS` blocks, for
+/// writes and allocations to map them in updates which fit into the journal.
+fn update_ranges(start: u64, end: u64) -> impl Iterator<Item = (u64, u64)> {
+    let span = (UPDATE_DATA_BLOCKS * BLOCK_SZ) as u64;
+    let mut pos = start;
+    core::iter::from_fn(move || {
+        if pos >= end {
+            return None;
+        }
+        let stop = ((pos / span + 1) * span).min(end);
+        let range = (pos, stop);
+        pos = stop;
+        Some(range)
+    })
+}
+
+impl Drop for Inode {
+    /// Queue a file unlinked while it was in use to be reclaimed, now that
+    /// nobody is. Nothing is written here, as the last one may be dropped
+    /// where no I/O can be done.
+    fn drop(&mut self) {
+        if *self.orphan.get_mut() {
+            self.inodes.orphans.lock().push(self.inode_id);
+        }
     }
 }
@@ -28,3 +28,7 @@ pub fn device_init() {
 pub fn irq_handler() {
     unimplemented!();
 }
+
+pub fn rtc_time_ms() -> u64 {
+    unimplemented!();
+}
@@ -1,6 +1,7 @@
 pub const CLOCK_FREQ: usize = 12500000;
 
 pub const MMIO: &[(usize, usize)] = &[
+    (0x0010_1000, 0x1000),
     (0x1000_0000, 0x1000),
     (0x1000_1000, 0x1000),
     (0xC00_0000, 0x40_0000),
@@ -11,6 +12,7 @@ pub type CharDeviceImpl = crate::drivers::chardev::NS16550a<VIRT_UART>;
 
 pub const VIRT_PLIC: usize = 0xC00_0000;
 pub const VIRT_UART: usize = 0x1000_0000;
+pub const VIRT_RTC: usize = 0x0010_1000;
 
 use crate::drivers::block::BLOCK_DEVICE;
 use crate::drivers::chardev::{CharDevice, UART};
@@ -43,3 +45,11 @@ pub fn irq_handler() {
     }
     plic.complete(0, IntrTargetPriority::Supervisor, intr_src_id);
 }
+
+/// Milliseconds since the Unix epoch from the Goldfish RTC, whose low word
+/// of nanoseconds is read first to latch the high one.
+pub fn rtc_time_ms() -> u64 {
+    let low = unsafe { core::ptr::read_volatile(VIRT_RTC as *const u32) };
+    let high = unsafe { core::ptr::read_volatile((VIRT_RTC + 4) as *const u32) };
+    (((high as u64) << 32) | low as u64) / 1_000_000
+}
@@ -3,10 +3,23 @@
 pub const USER_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_HEAP_SIZE: usize = 0x20_0000;
+/// Blocks of easy-fs kept in the write-back cache.
+pub const BLOCK_CACHE_CAPACITY: usize = 128;
+/// Milliseconds between two writes of all cached file data back to the disks.
+pub const FLUSH_INTERVAL_MS: usize = 5000;
+/// Pages of file data kept in the page caches of all inodes together, an
+/// eighth of the memory.
+pub const PAGE_CACHE_CAPACITY: usize = 256;
 pub const MEMORY_END: usize = 0x80800000;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 
+/// Mappings of `sys_mmap` are placed from here on, clear of the user stacks.
+pub const MMAP_BASE: usize = 0x10_0000_0000;
+/// The end of the lower half of an Sv39 address space, which is all a user
+/// program may map.
+pub const USER_SPACE_END: usize = 0x40_0000_0000;
+
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
 
@@ -0,0 +1,248 @@
+use super::{alloc_dev, File, FileSystem, Stdout, VfsInode};
+use crate::drivers::chardev::{CharDevice, UART};
+use crate::mm::UserBuffer;
+use crate::sync::UPIntrFreeCell;
+use crate::syscall::{Stat, StatMode};
+use crate::timer::get_time;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+
+/// A flat directory of character devices, which are opened as the devices themselves.
+pub struct DevFs {
+    root: Arc<DevDir>,
+}
+
+impl DevFs {
+    pub fn new() -> Arc<Self> {
+        let devices: Vec<(&'static str, Arc<dyn File>)> = vec![
+            ("null", Arc::new(NullDevice)),
+            ("zero", Arc::new(ZeroDevice)),
+            ("random", Arc::new(RandomDevice::new())),
+            ("tty", Arc::new(TtyDevice)),
+        ];
+        let dev = alloc_dev();
+        let root = Arc::new_cyclic(|this| DevDir {
+            this: this.clone(),
+            dev,
+            nodes: devices
+                .into_iter()
+                .enumerate()
+                .map(|(i, (name, device))| {
+                    Arc::new(DevNode {
+                        ino: i as u64 + 2,
+                        dev,
+                        name,
+                        device,
+                    })
+                })
+                .collect(),
+        });
+        Arc::new(Self { root })
+    }
+}
+
+impl FileSystem for DevFs {
+    fn root_inode(&self) -> Arc<dyn VfsInode> {
+        self.root.clone()
+    }
+}
+
+struct DevDir {
+    this: Weak<DevDir>,
+    dev: u64,
+    nodes: Vec<Arc<DevNode>>,
+}
+
+impl VfsInode for DevDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: self.dev,
+            ino: 1,
+            perm: 0o755,
+            nlink: 2,
+            ..Stat::anonymous(StatMode::DIR)
+        }
+    }
+    fn find(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        match name {
+            "." | ".." => Some(self.this.upgrade().unwrap()),
+            _ => self
+                .nodes
+                .iter()
+                .find(|node| node.name == name)
+                .map(|node| node.clone() as Arc<dyn VfsInode>),
+        }
+    }
+    /// Slot 0 and 1 are "." and "..", the devices follow.
+    fn read_dir(&self, slot: usize) -> Option<(usize, String, Arc<dyn VfsInode>)> {
+        let (name, inode): (&str, Arc<dyn VfsInode>) = match slot {
+            0 => (".", self.this.upgrade().unwrap()),
+            1 => ("..", self.this.upgrade().unwrap()),
+            _ => {
+                let node = self.nodes.get(slot - 2)?;
+                (node.name, node.clone())
+            }
+        };
+        Some((slot + 1, String::from(name), inode))
+    }
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> usize {
+        0
+    }
+}
+
+struct DevNode {
+    ino: u64,
+    dev: u64,
+    name: &'static str,
+    device: Arc<dyn File>,
+}
+
+impl VfsInode for DevNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: self.dev,
+            ino: self.ino,
+            perm: 0o666,
+            ..self.device.stat()
+        }
+    }
+    fn find(&self, _name: &str) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+    fn read_dir(&self, _slot: usize) -> Option<(usize, String, Arc<dyn VfsInode>)> {
+        None
+    }
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> usize {
+        0
+    }
+    fn device(&self) -> Option<Arc<dyn File>> {
+        Some(self.device.clone())
+    }
+}
+
+/// Discard everything written, reading always hits the end.
+pub struct NullDevice;
+
+impl File for NullDevice {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        buf.len()
+    }
+    fn stat(&self) -> Stat {
+        Stat::anonymous(StatMode::CHR)
+    }
+}
+
+/// An endless source of zero bytes, discarding everything written.
+pub struct ZeroDevice;
+
+impl File for ZeroDevice {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        for slice in buf.buffers.iter_mut() {
+            slice.fill(0);
+        }
+        buf.len()
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        buf.len()
+    }
+    fn stat(&self) -> Stat {
+        Stat::anonymous(StatMode::CHR)
+    }
+}
+
+/// Pseudo-random bytes from a xorshift generator seeded by the boot time,
+/// which is by no means suitable for cryptography.
+pub struct RandomDevice {
+    state: UPIntrFreeCell<u64>,
+}
+
+impl RandomDevice {
+    pub fn new() -> Self {
+        // the state of xorshift must not be zero
+        let seed = (get_time() as u64) | 1;
+        Self {
+            state: unsafe { UPIntrFreeCell::new(seed) },
+        }
+    }
+}
+
+impl File for RandomDevice {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, buf: UserBuffer) -> usize {
+        let mut state = self.state.exclusive_access();
+        let len = buf.len();
+        for byte in buf.into_iter() {
+            *state ^= *state << 13;
+            *state ^= *state >> 7;
+            *state ^= *state << 17;
+            unsafe {
+                *byte = *state as u8;
+            }
+        }
+        len
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        buf.len()
+    }
+    fn stat(&self) -> Stat {
+        Stat::anonymous(StatMode::CHR)
+    }
+}
+
+/// The console, reading it blocks for one character like `Stdin`.
+pub struct TtyDevice;
+
+impl File for TtyDevice {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn read(&self, buf: UserBuffer) -> usize {
+        match buf.into_iter().next() {
+            Some(byte) => {
+                unsafe {
+                    *byte = UART.read();
+                }
+                1
+            }
+            None => 0,
+        }
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        Stdout.write(buf)
+    }
+    fn stat(&self) -> Stat {
+        Stat::anonymous(StatMode::CHR)
+    }
+}
@@ -0,0 +1,182 @@
+use super::{alloc_dev, FileSystem, PageCache, VfsInode};
+use crate::config::BLOCK_CACHE_CAPACITY;
+use crate::sync::UPIntrFreeCell;
+use crate::syscall::{Stat, StatMode};
+use crate::task::suspend_current_and_run_next;
+use crate::timer::get_real_time_ms;
+use crate::DEV_NON_BLOCKING_ACCESS;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use core::any::Any;
+use easy_fs::{set_block_cache_capacity, set_clock, set_relax, BlockDevice, EasyFileSystem, Inode};
+
+/// easy-fs on a block device as a VFS backend.
+pub struct EasyFs {
+    root: Arc<EasyFsInode>,
+}
+
+/// Wait for a lock of easy-fs by running other tasks, as its holder may be
+/// waiting for the device.
+fn relax() {
+    if *DEV_NON_BLOCKING_ACCESS.exclusive_access() {
+        suspend_current_and_run_next();
+    }
+}
+
+impl EasyFs {
+    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Self> {
+        set_clock(get_real_time_ms);
+        set_relax(relax);
+        set_block_cache_capacity(BLOCK_CACHE_CAPACITY);
+        let efs = EasyFileSystem::open(block_device).expect("Error loading EFS!");
+        Arc::new(Self {
+            root: EasyFsInode::get(
+                EasyFileSystem::root_inode(&efs),
+                &Arc::new(unsafe { UPIntrFreeCell::new(BTreeMap::new()) }),
+                alloc_dev(),
+            ),
+        })
+    }
+}
+
+impl FileSystem for EasyFs {
+    fn root_inode(&self) -> Arc<dyn VfsInode> {
+        self.root.clone()
+    }
+    fn sync(&self) {
+        self.root.inode.sync();
+    }
+}
+
+/// The inodes of a filesystem in use by inode id, so that each file has one
+/// page cache however many times it is looked up.
+type InodeCache = UPIntrFreeCell<BTreeMap<u32, Weak<EasyFsInode>>>;
+
+pub struct EasyFsInode {
+    inode: Arc<Inode>,
+    pages: PageCache,
+    inodes: Arc<InodeCache>,
+    dev: u64,
+}
+
+impl EasyFsInode {
+    /// Return the inode in use for `inode` if there is one, otherwise a new one.
+    fn get(inode: Arc<Inode>, inodes: &Arc<InodeCache>, dev: u64) -> Arc<Self> {
+        let mut cache = inodes.exclusive_access();
+        let inode_id = inode.inode_id();
+        if let Some(inode) = cache.get(&inode_id).and_then(Weak::upgrade) {
+            return inode;
+        }
+        cache.retain(|_, inode| inode.strong_count() > 0);
+        let inode = Arc::new(Self {
+            inode,
+            pages: PageCache::new(),
+            inodes: Arc::clone(inodes),
+            dev,
+        });
+        cache.insert(inode_id, Arc::downgrade(&inode));
+        inode
+    }
+
+    fn wrap(&self, inode: Arc<Inode>) -> Arc<dyn VfsInode> {
+        Self::get(inode, &self.inodes, self.dev)
+    }
+
+    /// Get the easy-fs inode behind `inode`, `None` if it is from another filesystem.
+    fn downcast(inode: &Arc<dyn VfsInode>) -> Option<&Inode> {
+        inode
+            .as_any()
+            .downcast_ref::<Self>()
+            .map(|inode| inode.inode.as_ref())
+    }
+}
+
+impl VfsInode for EasyFsInode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn stat(&self) -> Stat {
+        let inode = &self.inode;
+        let (atime, mtime, ctime) = inode.times();
+        Stat {
+            dev: self.dev,
+            ino: inode.inode_id() as u64,
+            mode: if inode.is_dir() {
+                StatMode::DIR
+            } else {
+                StatMode::FILE
+            },
+            perm: inode.mode() as u32,
+            nlink: inode.nlink(),
+            pad: 0,
+            size: inode.size(),
+            blocks: inode.blocks(),
+            atime,
+            mtime,
+            ctime,
+        }
+    }
+    fn is_dir(&self) -> bool {
+        self.inode.is_dir()
+    }
+    fn size(&self) -> usize {
+        self.inode.size() as usize
+    }
+    fn find(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        self.inode.find(name).map(|inode| self.wrap(inode))
+    }
+    fn read_dir(&self, slot: usize) -> Option<(usize, String, Arc<dyn VfsInode>)> {
+        self.inode
+            .read_dir(slot)
+            .map(|(next, name, inode)| (next, name, self.wrap(inode)))
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        self.inode.read_at(offset, buf)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        self.inode.write_at(offset, buf)
+    }
+    fn clear(&self) {
+        self.pages.discard_with(|| self.inode.clear());
+    }
+    fn allocate(&self, offset: usize, len: usize) -> bool {
+        self.inode.allocate(offset, len)
+    }
+    fn punch_hole(&self, offset: usize, len: usize) -> bool {
+        self.pages
+            .discard_with(|| self.inode.punch_hole(offset, len))
+    }
+    /// The block cache is shared by all inodes and flushed as a whole,
+    /// along with the journal.
+    fn sync(&self) {
+        self.inode.sync();
+    }
+    fn create(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        self.inode.create(name).map(|inode| self.wrap(inode))
+    }
+    fn mkdir(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        self.inode.mkdir(name).map(|inode| self.wrap(inode))
+    }
+    fn rmdir(&self, name: &str) -> bool {
+        self.inode.rmdir(name)
+    }
+    fn link(&self, name: &str, inode: &Arc<dyn VfsInode>) -> bool {
+        Self::downcast(inode).map_or(false, |inode| self.inode.link(name, inode))
+    }
+    fn unlink(&self, name: &str) -> bool {
+        self.inode.unlink(name)
+    }
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn VfsInode>, new_name: &str) -> bool {
+        Self::downcast(new_dir).map_or(false, |new_dir| {
+            self.inode.rename(old_name, new_dir, new_name)
+        })
+    }
+    fn page_cache(&self) -> Option<&PageCache> {
+        if self.inode.is_dir() {
+            None
+        } else {
+            Some(&self.pages)
+        }
+    }
+}
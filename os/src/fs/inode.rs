@@ -1,30 +1,29 @@
-use super::File;
-use crate::drivers::BLOCK_DEVICE;
+use super::{is_mount_point, lookup, lookup_parent, Dirent, File, SeekFrom, VfsInode};
 use crate::mm::UserBuffer;
 use crate::sync::UPIntrFreeCell;
+use crate::syscall::Stat;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::*;
-use easy_fs::{EasyFileSystem, Inode};
-use lazy_static::*;
 
 pub struct OSInode {
     readable: bool,
     writable: bool,
+    inode: Arc<dyn VfsInode>,
     inner: UPIntrFreeCell<OSInodeInner>,
 }
 
 pub struct OSInodeInner {
     offset: usize,
-    inode: Arc<Inode>,
 }
 
 impl OSInode {
-    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+    pub fn new(readable: bool, writable: bool, inode: Arc<dyn VfsInode>) -> Self {
         Self {
             readable,
             writable,
-            inner: unsafe { UPIntrFreeCell::new(OSInodeInner { offset: 0, inode }) },
+            inode,
+            inner: unsafe { UPIntrFreeCell::new(OSInodeInner { offset: 0 }) },
         }
     }
     pub fn read_all(&self) -> Vec<u8> {
@@ -32,7 +31,7 @@ impl OSInode {
         let mut buffer = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
+            let len = self.inode.read_at(inner.offset, &mut buffer);
             if len == 0 {
                 break;
             }
@@ -43,17 +42,15 @@ impl OSInode {
     }
 }
 
-lazy_static! {
-    pub static ref ROOT_INODE: Arc<Inode> = {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
-        Arc::new(EasyFileSystem::root_inode(&efs))
-    };
-}
-
 pub fn list_apps() {
     println!("/**** APPS ****");
-    for app in ROOT_INODE.ls() {
-        println!("{}", app);
+    let root = lookup("/").unwrap();
+    let mut slot = 0;
+    while let Some((next, app, _)) = root.read_dir(slot) {
+        if app != "." && app != ".." {
+            println!("{}", app);
+        }
+        slot = next;
     }
     println!("**************/")
 }
@@ -82,26 +79,113 @@ impl OpenFlags {
     }
 }
 
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+pub fn open_file(path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
     if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
+        if let Some(inode) = lookup(path) {
+            if inode.is_dir() {
+                return None;
+            }
             // clear size
             inode.clear();
             Some(Arc::new(OSInode::new(readable, writable, inode)))
         } else {
             // create file
-            ROOT_INODE
-                .create(name)
+            let (parent, name) = lookup_parent(path)?;
+            parent
+                .create(name.as_str())
                 .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
         }
     } else {
-        ROOT_INODE.find(name).map(|inode| {
-            if flags.contains(OpenFlags::TRUNC) {
-                inode.clear();
-            }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
+        let inode = lookup(path)?;
+        // directories can only be opened for reading
+        if inode.is_dir() && (writable || flags.contains(OpenFlags::TRUNC)) {
+            return None;
+        }
+        if flags.contains(OpenFlags::TRUNC) {
+            inode.clear();
+        }
+        Some(Arc::new(OSInode::new(readable, writable, inode)))
+    }
+}
+
+/// A device opened through its inode, which is read and written as the
+/// device itself with the access of this open.
+struct DeviceFile {
+    readable: bool,
+    writable: bool,
+    inode: Arc<dyn VfsInode>,
+    device: Arc<dyn File>,
+}
+
+/// Open `path` for a file descriptor, device inodes are opened as the device itself.
+pub fn open(path: &str, flags: OpenFlags) -> Option<Arc<dyn File>> {
+    let file = open_file(path, flags)?;
+    match file.inode.device() {
+        Some(device) => Some(Arc::new(DeviceFile {
+            readable: file.readable,
+            writable: file.writable,
+            inode: file.inode.clone(),
+            device,
+        })),
+        None => Some(file),
+    }
+}
+
+pub fn is_dir(path: &str) -> bool {
+    lookup(path).map_or(false, |inode| inode.is_dir())
+}
+
+pub fn make_dir(path: &str) -> bool {
+    lookup_parent(path)
+        .and_then(|(parent, name)| parent.mkdir(name.as_str()))
+        .is_some()
+}
+
+pub fn remove_dir(path: &str) -> bool {
+    !is_mount_point(path)
+        && lookup_parent(path).map_or(false, |(parent, name)| parent.rmdir(name.as_str()))
+}
+
+pub fn link_file(old_path: &str, new_path: &str) -> bool {
+    match (lookup(old_path), lookup_parent(new_path)) {
+        (Some(inode), Some((parent, name))) => parent.link(name.as_str(), &inode),
+        _ => false,
+    }
+}
+
+pub fn unlink_file(path: &str) -> bool {
+    lookup_parent(path).map_or(false, |(parent, name)| parent.unlink(name.as_str()))
+}
+
+/// Mount points cannot be moved, neither can anything be moved onto them.
+pub fn rename_file(old_path: &str, new_path: &str) -> bool {
+    if is_mount_point(old_path) || is_mount_point(new_path) {
+        return false;
+    }
+    match (lookup_parent(old_path), lookup_parent(new_path)) {
+        (Some((old_parent, old_name)), Some((new_parent, new_name))) => {
+            old_parent.rename(old_name.as_str(), &new_parent, new_name.as_str())
+        }
+        _ => false,
+    }
+}
+
+impl File for DeviceFile {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
+    }
+    fn read(&self, buf: UserBuffer) -> usize {
+        self.device.read(buf)
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        self.device.write(buf)
+    }
+    fn stat(&self) -> Stat {
+        self.inode.stat()
     }
 }
 
@@ -112,28 +196,106 @@ impl File for OSInode {
     fn writable(&self) -> bool {
         self.writable
     }
-    fn read(&self, mut buf: UserBuffer) -> usize {
+    fn read(&self, buf: UserBuffer) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        let read_size = read_buffer(&self.inode, inner.offset, buf);
+        inner.offset += read_size;
+        read_size
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        let write_size = write_buffer(&self.inode, inner.offset, buf);
+        inner.offset += write_size;
+        write_size
+    }
+    fn stat(&self) -> Stat {
+        self.inode.stat()
+    }
+    fn seek(&self, pos: SeekFrom) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => offset_by(inner.offset, delta),
+            SeekFrom::End(delta) => offset_by(self.inode.stat().size as usize, delta),
+        }?;
+        inner.offset = offset;
+        Some(offset)
+    }
+    fn read_dir(&self, fill: &mut dyn FnMut(&Dirent) -> bool) -> Option<()> {
+        if !self.inode.is_dir() {
+            return None;
+        }
         let mut inner = self.inner.exclusive_access();
-        let mut total_read_size = 0usize;
-        for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
-            if read_size == 0 {
+        // the cursor of a directory counts entry slots instead of bytes
+        while let Some((next, name, inode)) = self.inode.read_dir(inner.offset) {
+            let stat = inode.stat();
+            let dirent = Dirent {
+                ino: stat.ino,
+                next,
+                mode: stat.mode,
+                name,
+            };
+            if !fill(&dirent) {
                 break;
             }
-            inner.offset += read_size;
-            total_read_size += read_size;
+            inner.offset = next;
         }
-        total_read_size
+        Some(())
     }
-    fn write(&self, buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
-        let mut total_write_size = 0usize;
-        for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
-            total_write_size += write_size;
+    fn read_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        Some(read_buffer(&self.inode, offset, buf))
+    }
+    fn write_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        Some(write_buffer(&self.inode, offset, buf))
+    }
+    fn sync(&self) -> Option<()> {
+        self.inode.sync();
+        Some(())
+    }
+    fn allocate(&self, offset: usize, len: usize) -> bool {
+        self.inode.allocate(offset, len)
+    }
+    fn punch_hole(&self, offset: usize, len: usize) -> bool {
+        self.inode.punch_hole(offset, len)
+    }
+}
+
+fn offset_by(base: usize, delta: isize) -> Option<usize> {
+    if delta >= 0 {
+        base.checked_add(delta as usize)
+    } else {
+        base.checked_sub(delta.unsigned_abs())
+    }
+}
+
+fn read_buffer(inode: &Arc<dyn VfsInode>, mut offset: usize, mut buf: UserBuffer) -> usize {
+    let mut total_read_size = 0usize;
+    for slice in buf.buffers.iter_mut() {
+        let read_size = match inode.page_cache() {
+            Some(pages) => pages.read(inode.as_ref(), offset, *slice),
+            None => inode.read_at(offset, *slice),
+        };
+        if read_size == 0 {
+            break;
+        }
+        offset += read_size;
+        total_read_size += read_size;
+    }
+    total_read_size
+}
+
+fn write_buffer(inode: &Arc<dyn VfsInode>, mut offset: usize, buf: UserBuffer) -> usize {
+    let mut total_write_size = 0usize;
+    for slice in buf.buffers.iter() {
+        let write_size = match inode.page_cache() {
+            Some(pages) => pages.write(inode.as_ref(), offset, *slice),
+            None => inode.write_at(offset, *slice),
+        };
+        offset += write_size;
+        total_write_size += write_size;
+        if write_size < slice.len() {
+            break;
         }
-        total_write_size
     }
+    total_write_size
 }
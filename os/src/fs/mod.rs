@@ -1,16 +1,43 @@
+mod devfs;
+mod easyfs;
 mod inode;
+mod mount;
+mod page_cache;
+mod path;
 mod pipe;
+mod procfs;
 mod stdio;
+mod tmpfs;
+mod vfs;
 
-use crate::mm::UserBuffer;
-
-pub trait File: Send + Sync {
-    fn readable(&self) -> bool;
-    fn writable(&self) -> bool;
-    fn read(&self, buf: UserBuffer) -> usize;
-    fn write(&self, buf: UserBuffer) -> usize;
-}
-
-pub use inode::{list_apps, open_file, OSInode, OpenFlags};
+pub use devfs::DevFs;
+pub use easyfs::EasyFs;
+pub use inode::{
+    is_dir, link_file, list_apps, make_dir, open, open_file, remove_dir, rename_file, unlink_file,
+    OSInode, OpenFlags,
+};
+pub use mount::{flush_due, is_mount_point, lookup, lookup_parent, mount, sync_all, umount};
+pub use page_cache::PageCache;
+pub use path::absolute_path;
 pub use pipe::{make_pipe, Pipe};
+pub use procfs::ProcFs;
 pub use stdio::{Stdin, Stdout};
+pub use tmpfs::TmpFs;
+pub use vfs::{alloc_dev, Dirent, File, FileSystem, SeekFrom, VfsInode};
+
+/// Mount the in-memory filesystems, their mount points are created in the
+/// root filesystem on the first boot.
+pub fn init() {
+    if !is_dir("/tmp") {
+        assert!(make_dir("/tmp"));
+    }
+    assert!(mount("/tmp", TmpFs::new()));
+    if !is_dir("/dev") {
+        assert!(make_dir("/dev"));
+    }
+    assert!(mount("/dev", DevFs::new()));
+    if !is_dir("/proc") {
+        assert!(make_dir("/proc"));
+    }
+    assert!(mount("/proc", ProcFs::new()));
+}
@@ -0,0 +1,134 @@
+use super::{absolute_path, EasyFs, FileSystem, VfsInode};
+use crate::config::FLUSH_INTERVAL_MS;
+use crate::drivers::BLOCK_DEVICE;
+use crate::sync::UPIntrFreeCell;
+use crate::timer::get_time_ms;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+
+struct Mount {
+    /// absolute path of the mount point
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+lazy_static! {
+    /// easy-fs on the block device is always mounted at "/".
+    static ref MOUNT_TABLE: UPIntrFreeCell<Vec<Mount>> = unsafe {
+        UPIntrFreeCell::new(vec![Mount {
+            path: String::from("/"),
+            fs: EasyFs::open(BLOCK_DEVICE.clone()),
+        }])
+    };
+}
+
+/// Whether `path` is `mount_path` itself or lies under it.
+fn is_under(path: &str, mount_path: &str) -> bool {
+    mount_path == "/"
+        || (path.starts_with(mount_path)
+            && matches!(path.as_bytes().get(mount_path.len()), None | Some(b'/')))
+}
+
+/// Mount `fs` on the existing directory `path`, which must not be a mount point yet.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> bool {
+    let path = absolute_path("/", path);
+    if !lookup(path.as_str()).map_or(false, |inode| inode.is_dir()) {
+        return false;
+    }
+    let mut mount_table = MOUNT_TABLE.exclusive_access();
+    if mount_table.iter().any(|mount| mount.path == path) {
+        return false;
+    }
+    mount_table.push(Mount { path, fs });
+    true
+}
+
+/// Detach the filesystem mounted at `path`, the root and mount points with
+/// other filesystems mounted below them cannot be detached.
+#[allow(unused)]
+pub fn umount(path: &str) -> bool {
+    let path = absolute_path("/", path);
+    if path == "/" {
+        return false;
+    }
+    let mut mount_table = MOUNT_TABLE.exclusive_access();
+    let busy = mount_table
+        .iter()
+        .any(|mount| mount.path != path && is_under(mount.path.as_str(), path.as_str()));
+    match mount_table.iter().position(|mount| mount.path == path) {
+        Some(index) if !busy => {
+            mount_table.remove(index);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Write the cached data of all mounted filesystems back.
+pub fn sync_all() {
+    let filesystems: Vec<Arc<dyn FileSystem>> = MOUNT_TABLE
+        .exclusive_access()
+        .iter()
+        .map(|mount| mount.fs.clone())
+        .collect();
+    for fs in filesystems {
+        fs.sync();
+    }
+}
+
+/// When all filesystems were last written back from time to time, in milliseconds.
+static LAST_FLUSH_MS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether `FLUSH_INTERVAL_MS` have passed since the last time this returned
+/// true, so that the caller writes everything back by `sync_all`.
+pub fn flush_due() -> bool {
+    let now = get_time_ms();
+    let last = LAST_FLUSH_MS.load(Ordering::Relaxed);
+    now.saturating_sub(last) >= FLUSH_INTERVAL_MS
+        && LAST_FLUSH_MS
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+}
+
+pub fn is_mount_point(path: &str) -> bool {
+    let path = absolute_path("/", path);
+    MOUNT_TABLE
+        .exclusive_access()
+        .iter()
+        .any(|mount| mount.path == path)
+}
+
+/// Walk a path from the root, crossing into the filesystem mounted deepest on it.
+/// A relative path is taken as relative to the root.
+pub fn lookup(path: &str) -> Option<Arc<dyn VfsInode>> {
+    let path = absolute_path("/", path);
+    let (mount_path_len, root) = {
+        let mount_table = MOUNT_TABLE.exclusive_access();
+        let mount = mount_table
+            .iter()
+            .filter(|mount| is_under(path.as_str(), mount.path.as_str()))
+            .max_by_key(|mount| mount.path.len())
+            .unwrap();
+        (mount.path.len(), mount.fs.root_inode())
+    };
+    path[mount_path_len..]
+        .split('/')
+        .filter(|name| !name.is_empty())
+        .try_fold(root, |inode, name| inode.find(name))
+}
+
+/// Split the path into its parent directory and the last component.
+pub fn lookup_parent(path: &str) -> Option<(Arc<dyn VfsInode>, String)> {
+    let path = absolute_path("/", path);
+    let pos = path.rfind('/').unwrap();
+    let name = &path[pos + 1..];
+    if name.is_empty() {
+        return None;
+    }
+    let parent = if pos == 0 { "/" } else { &path[..pos] };
+    lookup(parent).map(|inode| (inode, String::from(name)))
+}
@@ -0,0 +1,135 @@
+use super::VfsInode;
+use crate::config::{PAGE_CACHE_CAPACITY, PAGE_SIZE};
+use crate::mm::{frame_alloc, FrameTracker};
+use crate::sync::{Mutex, MutexSpin, UPIntrFreeCell};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Pages held by all page caches, which are limited to `PAGE_CACHE_CAPACITY`
+/// together so that they leave the frames to the address spaces.
+static CACHED_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// The data of a file kept in whole frames, so that reads and writes through
+/// files share it with the pages mapped into address spaces later on.
+///
+/// Writes go through to the inode and update the pages they cover, so nothing
+/// is lost if the pages are dropped. Anything else changing the data of the
+/// inode should go through `discard_with`.
+pub struct PageCache {
+    /// held while pages are loaded or the data changes, which may switch tasks
+    lock: MutexSpin,
+    /// (page index, page) from the least recently used to the most recently used
+    pages: UPIntrFreeCell<VecDeque<(usize, Arc<FrameTracker>)>>,
+}
+
+impl PageCache {
+    pub fn new() -> Self {
+        Self {
+            lock: MutexSpin::new(),
+            pages: unsafe { UPIntrFreeCell::new(VecDeque::new()) },
+        }
+    }
+
+    /// Return the page at `index`, loading it from `inode` if it is not cached,
+    /// `None` if there is no room or no frame left for it. The caller holds `lock`.
+    fn page(&self, inode: &dyn VfsInode, index: usize) -> Option<Arc<FrameTracker>> {
+        let mut pages = self.pages.exclusive_access();
+        if let Some(idx) = pages.iter().position(|pair| pair.0 == index) {
+            // move to the tail as the most recently used
+            let pair = pages.remove(idx).unwrap();
+            let page = Arc::clone(&pair.1);
+            pages.push_back(pair);
+            return Some(page);
+        }
+        // once all caches are full a page of this one makes room, pages mapped
+        // somewhere stay until they are unmapped
+        if CACHED_PAGES.load(Ordering::Relaxed) >= PAGE_CACHE_CAPACITY {
+            let idx = pages
+                .iter()
+                .position(|pair| Arc::strong_count(&pair.1) == 1)?;
+            pages.remove(idx);
+        } else {
+            CACHED_PAGES.fetch_add(1, Ordering::Relaxed);
+        }
+        drop(pages);
+        // what is beyond the end of the file is left zeroed
+        let page = match frame_alloc() {
+            Some(frame) => Arc::new(frame),
+            None => {
+                CACHED_PAGES.fetch_sub(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+        inode.read_at(index * PAGE_SIZE, page.ppn.get_bytes_array());
+        self.pages
+            .exclusive_access()
+            .push_back((index, Arc::clone(&page)));
+        Some(page)
+    }
+
+    /// Read from `offset` into `buf` up to the end of the file.
+    pub fn read(&self, inode: &dyn VfsInode, offset: usize, buf: &mut [u8]) -> usize {
+        self.lock.lock();
+        let size = inode.size();
+        let end = offset.saturating_add(buf.len()).min(size);
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match self.page(inode, pos / PAGE_SIZE) {
+                Some(page) => {
+                    dst.copy_from_slice(&page.ppn.get_bytes_array()[page_offset..page_offset + len])
+                }
+                // short of room or frames, read around the pages
+                None => {
+                    inode.read_at(pos, dst);
+                }
+            }
+            pos += len;
+        }
+        self.lock.unlock();
+        end.saturating_sub(offset)
+    }
+
+    /// Write `buf` at `offset` to the inode and to the cached pages it covers.
+    pub fn write(&self, inode: &dyn VfsInode, offset: usize, buf: &[u8]) -> usize {
+        self.lock.lock();
+        let write_size = inode.write_at(offset, buf);
+        let end = offset + write_size;
+        for (index, page) in self.pages.exclusive_access().iter() {
+            let page_start = index * PAGE_SIZE;
+            let start = page_start.max(offset);
+            let stop = (page_start + PAGE_SIZE).min(end);
+            if start < stop {
+                page.ppn.get_bytes_array()[start - page_start..stop - page_start]
+                    .copy_from_slice(&buf[start - offset..stop - offset]);
+            }
+        }
+        self.lock.unlock();
+        write_size
+    }
+
+    /// Change the data of the inode by `f` other than by writing, the pages
+    /// are dropped as they may no longer match.
+    pub fn discard_with<V>(&self, f: impl FnOnce() -> V) -> V {
+        self.lock.lock();
+        let ret = f();
+        self.clear();
+        self.lock.unlock();
+        ret
+    }
+
+    fn clear(&self) {
+        let mut pages = self.pages.exclusive_access();
+        CACHED_PAGES.fetch_sub(pages.len(), Ordering::Relaxed);
+        pages.clear();
+    }
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
@@ -0,0 +1,28 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Join a relative `path` to `cwd` and resolve "." and ".." lexically.
+///
+/// The result always starts with "/" and has no trailing "/" except for the root.
+pub fn absolute_path(cwd: &str, path: &str) -> String {
+    let base = if path.starts_with('/') { "" } else { cwd };
+    let mut names: Vec<&str> = Vec::new();
+    for name in base.split('/').chain(path.split('/')) {
+        match name {
+            "" | "." => {}
+            ".." => {
+                names.pop();
+            }
+            _ => names.push(name),
+        }
+    }
+    if names.is_empty() {
+        return String::from("/");
+    }
+    let mut abs_path = String::new();
+    for name in names {
+        abs_path.push('/');
+        abs_path.push_str(name);
+    }
+    abs_path
+}
@@ -1,6 +1,7 @@
 use super::File;
 use crate::mm::UserBuffer;
 use crate::sync::UPIntrFreeCell;
+use crate::syscall::{Stat, StatMode};
 use alloc::sync::{Arc, Weak};
 
 use crate::task::suspend_current_and_run_next;
@@ -163,4 +164,7 @@ impl File for Pipe {
             }
         }
     }
+    fn stat(&self) -> Stat {
+        Stat::anonymous(StatMode::FIFO)
+    }
 }
@@ -0,0 +1,302 @@
+use super::{alloc_dev, FileSystem, VfsInode};
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_usage, MapPermission};
+use crate::syscall::{Stat, StatMode};
+use crate::task::{current_process, pid2process, pid_list, TaskStatus};
+use crate::timer::get_time_ms;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt::Write;
+
+/// Kernel state rendered as text, which is generated again on every read.
+///
+/// ```text
+/// /proc/meminfo       frames of the frame allocator
+/// /proc/uptime        seconds since boot
+/// /proc/self          the directory of the calling process
+/// /proc/<pid>/status  state, parent, threads and open files
+/// /proc/<pid>/maps    areas of the address space
+/// ```
+pub struct ProcFs {
+    dev: u64,
+}
+
+impl ProcFs {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self { dev: alloc_dev() })
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn root_inode(&self) -> Arc<dyn VfsInode> {
+        ProcInode::new(ProcKind::Root, self.dev)
+    }
+}
+
+#[derive(Copy, Clone)]
+enum ProcKind {
+    Root,
+    MemInfo,
+    Uptime,
+    PidDir(usize),
+    Status(usize),
+    Maps(usize),
+}
+
+/// Entries of the root before the pid directories.
+const ROOT_ENTRIES: [(&str, ProcKind); 2] =
+    [("meminfo", ProcKind::MemInfo), ("uptime", ProcKind::Uptime)];
+/// Slot of the first pid directory in the root, "." and ".." and "self" come
+/// before it.
+const FIRST_PID_SLOT: usize = ROOT_ENTRIES.len() + 3;
+
+/// Inodes hold no state and are made up on every lookup.
+struct ProcInode {
+    kind: ProcKind,
+    dev: u64,
+}
+
+impl ProcInode {
+    fn new(kind: ProcKind, dev: u64) -> Arc<Self> {
+        Arc::new(Self { kind, dev })
+    }
+
+    fn ino(&self) -> u64 {
+        match self.kind {
+            ProcKind::Root => 1,
+            ProcKind::MemInfo => 2,
+            ProcKind::Uptime => 3,
+            ProcKind::PidDir(pid) => (pid as u64 + 1) * 4,
+            ProcKind::Status(pid) => (pid as u64 + 1) * 4 + 1,
+            ProcKind::Maps(pid) => (pid as u64 + 1) * 4 + 2,
+        }
+    }
+
+    /// Text of a file, `None` if it is a directory or the process has exited.
+    fn content(&self) -> Option<String> {
+        match self.kind {
+            ProcKind::Root | ProcKind::PidDir(_) => None,
+            ProcKind::MemInfo => Some(meminfo()),
+            ProcKind::Uptime => {
+                let ms = get_time_ms();
+                Some(format!("{}.{:03}\n", ms / 1000, ms % 1000))
+            }
+            ProcKind::Status(pid) => status(pid),
+            ProcKind::Maps(pid) => maps(pid),
+        }
+    }
+}
+
+impl VfsInode for ProcInode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn stat(&self) -> Stat {
+        match self.kind {
+            ProcKind::Root | ProcKind::PidDir(_) => Stat {
+                dev: self.dev,
+                ino: self.ino(),
+                perm: 0o555,
+                nlink: 2,
+                ..Stat::anonymous(StatMode::DIR)
+            },
+            _ => Stat {
+                dev: self.dev,
+                ino: self.ino(),
+                perm: 0o444,
+                ..Stat::anonymous(StatMode::FILE)
+            },
+        }
+    }
+    fn find(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        let kind = match (self.kind, name) {
+            (ProcKind::Root, "." | "..") => ProcKind::Root,
+            (ProcKind::Root, "self") => ProcKind::PidDir(current_process().getpid()),
+            (ProcKind::Root, _) => match ROOT_ENTRIES.iter().find(|(entry, _)| *entry == name) {
+                Some((_, kind)) => *kind,
+                None => {
+                    let pid = name.parse().ok()?;
+                    pid2process(pid)?;
+                    ProcKind::PidDir(pid)
+                }
+            },
+            (ProcKind::PidDir(pid), ".") => ProcKind::PidDir(pid),
+            (ProcKind::PidDir(_), "..") => ProcKind::Root,
+            (ProcKind::PidDir(pid), "status") => ProcKind::Status(pid),
+            (ProcKind::PidDir(pid), "maps") => ProcKind::Maps(pid),
+            _ => return None,
+        };
+        Some(ProcInode::new(kind, self.dev))
+    }
+    /// The pid directories are listed from slot `FIRST_PID_SLOT` on, each one
+    /// at that slot plus its pid so that processes coming and going between
+    /// calls do not shift the others.
+    fn read_dir(&self, slot: usize) -> Option<(usize, String, Arc<dyn VfsInode>)> {
+        let (next, name, kind) = match (self.kind, slot) {
+            (ProcKind::Root, 0) => (1, String::from("."), ProcKind::Root),
+            (ProcKind::Root, 1) => (2, String::from(".."), ProcKind::Root),
+            (ProcKind::Root, 2) => (
+                3,
+                String::from("self"),
+                ProcKind::PidDir(current_process().getpid()),
+            ),
+            (ProcKind::Root, _) if slot < FIRST_PID_SLOT => {
+                let (name, kind) = ROOT_ENTRIES[slot - 3];
+                (slot + 1, String::from(name), kind)
+            }
+            (ProcKind::Root, _) => {
+                let pid = pid_list()
+                    .into_iter()
+                    .find(|pid| *pid >= slot - FIRST_PID_SLOT)?;
+                (
+                    FIRST_PID_SLOT + pid + 1,
+                    format!("{}", pid),
+                    ProcKind::PidDir(pid),
+                )
+            }
+            (ProcKind::PidDir(pid), 0) => (1, String::from("."), ProcKind::PidDir(pid)),
+            (ProcKind::PidDir(_), 1) => (2, String::from(".."), ProcKind::Root),
+            (ProcKind::PidDir(pid), 2) => (3, String::from("status"), ProcKind::Status(pid)),
+            (ProcKind::PidDir(pid), 3) => (4, String::from("maps"), ProcKind::Maps(pid)),
+            _ => return None,
+        };
+        Some((next, name, ProcInode::new(kind, self.dev)))
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let content = match self.content() {
+            Some(content) => content,
+            None => return 0,
+        };
+        let content = content.as_bytes();
+        if offset >= content.len() {
+            return 0;
+        }
+        let len = buf.len().min(content.len() - offset);
+        buf[..len].copy_from_slice(&content[offset..offset + len]);
+        len
+    }
+}
+
+fn meminfo() -> String {
+    let (total, free) = frame_usage();
+    let kib = PAGE_SIZE / 1024;
+    format!(
+        "MemTotal:\t{} kB\nMemFree:\t{} kB\n",
+        total * kib,
+        free * kib
+    )
+}
+
+fn task_status(status: TaskStatus) -> &'static str {
+    match status {
+        TaskStatus::Running => "running",
+        TaskStatus::Ready => "ready",
+        TaskStatus::Blocking => "blocking",
+    }
+}
+
+fn file_type(mode: StatMode) -> &'static str {
+    if mode.contains(StatMode::DIR) {
+        "dir"
+    } else if mode.contains(StatMode::FILE) {
+        "file"
+    } else if mode.contains(StatMode::CHR) {
+        "chr"
+    } else if mode.contains(StatMode::FIFO) {
+        "fifo"
+    } else {
+        "?"
+    }
+}
+
+/// One `Key:\tvalue` line per field. The process is running if any of its
+/// threads is, otherwise ready if any of them is, otherwise blocking.
+fn status(pid: usize) -> Option<String> {
+    let process = pid2process(pid)?;
+    let inner = process.inner_exclusive_access();
+    let ppid = inner
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+        .map_or(0, |parent| parent.getpid());
+    let threads: Vec<(usize, TaskStatus)> = inner
+        .tasks
+        .iter()
+        .enumerate()
+        .filter_map(|(tid, task)| {
+            task.as_ref()
+                .map(|task| (tid, task.inner_exclusive_access().task_status))
+        })
+        .collect();
+    let state = if inner.is_zombie {
+        "zombie"
+    } else if threads
+        .iter()
+        .any(|(_, status)| *status == TaskStatus::Running)
+    {
+        "running"
+    } else if threads
+        .iter()
+        .any(|(_, status)| *status == TaskStatus::Ready)
+    {
+        "ready"
+    } else {
+        "blocking"
+    };
+    let vm_size: usize = inner
+        .memory_set
+        .areas()
+        .map(|(start, end, _)| end.0 - start.0)
+        .sum();
+    let mut text = String::new();
+    writeln!(text, "Pid:\t{}", pid).unwrap();
+    writeln!(text, "PPid:\t{}", ppid).unwrap();
+    writeln!(text, "State:\t{}", state).unwrap();
+    writeln!(text, "Threads:\t{}", threads.len()).unwrap();
+    text.push_str("Tasks:\t");
+    for (i, (tid, status)) in threads.iter().enumerate() {
+        let sep = if i == 0 { "" } else { " " };
+        write!(text, "{}{}:{}", sep, tid, task_status(*status)).unwrap();
+    }
+    text.push('\n');
+    writeln!(text, "VmSize:\t{} kB", vm_size / 1024).unwrap();
+    writeln!(text, "Cwd:\t{}", inner.cwd).unwrap();
+    text.push_str("Fds:\t");
+    let files = inner
+        .fd_table
+        .iter()
+        .enumerate()
+        .filter_map(|(fd, file)| file.as_ref().map(|file| (fd, file)));
+    for (i, (fd, file)) in files.enumerate() {
+        let sep = if i == 0 { "" } else { " " };
+        write!(text, "{}{}:{}", sep, fd, file_type(file.stat().mode)).unwrap();
+    }
+    text.push('\n');
+    Some(text)
+}
+
+/// One `start-end perm` line per area, where the permission reads like
+/// `rwxu` with `-` for each missing bit and `u` for user accessible.
+fn maps(pid: usize) -> Option<String> {
+    let process = pid2process(pid)?;
+    let inner = process.inner_exclusive_access();
+    let mut text = String::new();
+    for (start, end, perm) in inner.memory_set.areas() {
+        let bit = |flag: MapPermission, ch: char| if perm.contains(flag) { ch } else { '-' };
+        writeln!(
+            text,
+            "{:016x}-{:016x} {}{}{}{}",
+            start.0,
+            end.0,
+            bit(MapPermission::R, 'r'),
+            bit(MapPermission::W, 'w'),
+            bit(MapPermission::X, 'x'),
+            bit(MapPermission::U, 'u'),
+        )
+        .unwrap();
+    }
+    Some(text)
+}
@@ -1,6 +1,7 @@
 use super::File;
 use crate::drivers::chardev::{CharDevice, UART};
 use crate::mm::UserBuffer;
+use crate::syscall::{Stat, StatMode};
 
 pub struct Stdin;
 pub struct Stdout;
@@ -24,6 +25,9 @@ impl File for Stdin {
     fn write(&self, _user_buf: UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
+    fn stat(&self) -> Stat {
+        Stat::anonymous(StatMode::CHR)
+    }
 }
 
 impl File for Stdout {
@@ -42,4 +46,7 @@ impl File for Stdout {
         }
         user_buf.len()
     }
+    fn stat(&self) -> Stat {
+        Stat::anonymous(StatMode::CHR)
+    }
 }
@@ -0,0 +1,407 @@
+use super::{alloc_dev, FileSystem, VfsInode};
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_alloc, FrameTracker};
+use crate::sync::UPIntrFreeCell;
+use crate::syscall::{Stat, StatMode};
+use crate::timer::get_real_time_ms;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// A filesystem living in memory, its content is lost on reboot.
+///
+/// File data is kept in whole frames from `frame_alloc`, nothing is ever written
+/// to the disk.
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+    pub fn new() -> Arc<Self> {
+        let root = TmpInode::new(TmpInodeKind::Directory, 0o1777, alloc_dev(), None);
+        Arc::new(Self { root })
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn root_inode(&self) -> Arc<dyn VfsInode> {
+        self.root.clone()
+    }
+}
+
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Clone, Copy, PartialEq)]
+enum TmpInodeKind {
+    File,
+    Directory,
+}
+
+pub struct TmpInode {
+    ino: u64,
+    /// device id of the filesystem
+    dev: u64,
+    kind: TmpInodeKind,
+    /// refers to itself to hand out "." and to be the parent of sub-directories
+    this: Weak<TmpInode>,
+    inner: UPIntrFreeCell<TmpInodeInner>,
+}
+
+pub struct TmpInodeInner {
+    size: usize,
+    nlink: u32,
+    mode: u16,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+    /// data pages of a file
+    frames: Vec<FrameTracker>,
+    /// entries of a directory except "." and ".."
+    entries: Vec<(String, Arc<TmpInode>)>,
+    /// the root is its own parent
+    parent: Weak<TmpInode>,
+}
+
+impl TmpInodeInner {
+    fn find(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|(entry, _)| entry == name)
+    }
+
+    fn touch_modified(&mut self) {
+        self.mtime = get_real_time_ms();
+        self.ctime = self.mtime;
+    }
+}
+
+impl TmpInode {
+    /// The inode without a parent is the root.
+    fn new(kind: TmpInodeKind, mode: u16, dev: u64, parent: Option<Weak<TmpInode>>) -> Arc<Self> {
+        let now = get_real_time_ms();
+        Arc::new_cyclic(|this| Self {
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            dev,
+            kind,
+            this: this.clone(),
+            inner: unsafe {
+                UPIntrFreeCell::new(TmpInodeInner {
+                    size: 0,
+                    nlink: match kind {
+                        TmpInodeKind::File => 1,
+                        TmpInodeKind::Directory => 2,
+                    },
+                    mode,
+                    atime: now,
+                    mtime: now,
+                    ctime: now,
+                    frames: Vec::new(),
+                    entries: Vec::new(),
+                    parent: parent.unwrap_or_else(|| this.clone()),
+                })
+            },
+        })
+    }
+
+    fn this(&self) -> Arc<TmpInode> {
+        self.this.upgrade().unwrap()
+    }
+
+    fn downcast(inode: &Arc<dyn VfsInode>) -> Option<&TmpInode> {
+        inode.as_any().downcast_ref::<Self>()
+    }
+
+    fn create_inode(&self, name: &str, kind: TmpInodeKind) -> Option<Arc<dyn VfsInode>> {
+        if self.kind != TmpInodeKind::Directory || name == "." || name == ".." {
+            return None;
+        }
+        let mut inner = self.inner.exclusive_access();
+        if inner.find(name).is_some() {
+            return None;
+        }
+        let mode = match kind {
+            TmpInodeKind::File => 0o644,
+            TmpInodeKind::Directory => 0o755,
+        };
+        let inode = TmpInode::new(kind, mode, self.dev, Some(self.this.clone()));
+        inner.entries.push((String::from(name), inode.clone()));
+        if kind == TmpInodeKind::Directory {
+            inner.nlink += 1;
+        }
+        inner.touch_modified();
+        Some(inode)
+    }
+
+    /// Whether `self` is `inode` or one of its descendants.
+    fn is_descendant_of(&self, inode: &TmpInode) -> bool {
+        let mut current = self.this();
+        loop {
+            if current.ino == inode.ino {
+                return true;
+            }
+            let parent = current.inner.exclusive_access().parent.upgrade().unwrap();
+            if parent.ino == current.ino {
+                return false;
+            }
+            current = parent;
+        }
+    }
+}
+
+impl VfsInode for TmpInode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn stat(&self) -> Stat {
+        let inner = self.inner.exclusive_access();
+        Stat {
+            dev: self.dev,
+            ino: self.ino,
+            mode: match self.kind {
+                TmpInodeKind::File => StatMode::FILE,
+                TmpInodeKind::Directory => StatMode::DIR,
+            },
+            perm: inner.mode as u32,
+            nlink: inner.nlink,
+            pad: 0,
+            size: inner.size as u64,
+            blocks: (inner.frames.len() * (PAGE_SIZE / 512)) as u64,
+            atime: inner.atime,
+            mtime: inner.mtime,
+            ctime: inner.ctime,
+        }
+    }
+    fn is_dir(&self) -> bool {
+        self.kind == TmpInodeKind::Directory
+    }
+    fn find(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        if self.kind != TmpInodeKind::Directory {
+            return None;
+        }
+        let inner = self.inner.exclusive_access();
+        let inode = match name {
+            "." => self.this(),
+            ".." => inner.parent.upgrade().unwrap(),
+            _ => inner.entries[inner.find(name)?].1.clone(),
+        };
+        Some(inode)
+    }
+    /// Slot 0 and 1 are "." and "..", the entries follow.
+    fn read_dir(&self, slot: usize) -> Option<(usize, String, Arc<dyn VfsInode>)> {
+        if self.kind != TmpInodeKind::Directory {
+            return None;
+        }
+        let inner = self.inner.exclusive_access();
+        let (name, inode) = match slot {
+            0 => (String::from("."), self.this()),
+            1 => (String::from(".."), inner.parent.upgrade().unwrap()),
+            _ => {
+                let (name, inode) = inner.entries.get(slot - 2)?;
+                (name.clone(), inode.clone())
+            }
+        };
+        Some((slot + 1, name, inode as Arc<dyn VfsInode>))
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        if self.kind != TmpInodeKind::File {
+            return 0;
+        }
+        let mut inner = self.inner.exclusive_access();
+        inner.atime = get_real_time_ms();
+        let end = inner.size.min(offset + buf.len());
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let page = inner.frames[pos / PAGE_SIZE].ppn.get_bytes_array();
+            buf[pos - offset..pos - offset + len]
+                .copy_from_slice(&page[page_offset..page_offset + len]);
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+    /// The write stops short when frames run out.
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        if self.kind != TmpInodeKind::File {
+            return 0;
+        }
+        let mut inner = self.inner.exclusive_access();
+        let end = offset + buf.len();
+        while inner.frames.len() * PAGE_SIZE < end {
+            match frame_alloc() {
+                Some(frame) => inner.frames.push(frame),
+                None => break,
+            }
+        }
+        let end = end.min(inner.frames.len() * PAGE_SIZE);
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let page = inner.frames[pos / PAGE_SIZE].ppn.get_bytes_array();
+            page[page_offset..page_offset + len]
+                .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        inner.size = inner.size.max(end);
+        inner.touch_modified();
+        end.saturating_sub(offset)
+    }
+    fn clear(&self) {
+        let mut inner = self.inner.exclusive_access();
+        inner.frames.clear();
+        inner.size = 0;
+        inner.touch_modified();
+    }
+    fn create(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        self.create_inode(name, TmpInodeKind::File)
+    }
+    fn mkdir(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        self.create_inode(name, TmpInodeKind::Directory)
+    }
+    fn rmdir(&self, name: &str) -> bool {
+        if self.kind != TmpInodeKind::Directory {
+            return false;
+        }
+        let mut inner = self.inner.exclusive_access();
+        let index = match inner.find(name) {
+            Some(index) => index,
+            None => return false,
+        };
+        let dir = &inner.entries[index].1;
+        if dir.kind != TmpInodeKind::Directory || !dir.inner.exclusive_access().entries.is_empty() {
+            return false;
+        }
+        inner.entries.remove(index);
+        inner.nlink -= 1;
+        inner.touch_modified();
+        true
+    }
+    /// Hard links to directories are not allowed.
+    fn link(&self, name: &str, inode: &Arc<dyn VfsInode>) -> bool {
+        let inode = match Self::downcast(inode) {
+            Some(inode) if inode.kind == TmpInodeKind::File => inode.this(),
+            _ => return false,
+        };
+        if self.kind != TmpInodeKind::Directory || name == "." || name == ".." {
+            return false;
+        }
+        let mut inner = self.inner.exclusive_access();
+        if inner.find(name).is_some() {
+            return false;
+        }
+        inner.entries.push((String::from(name), inode.clone()));
+        inner.touch_modified();
+        let mut inode_inner = inode.inner.exclusive_access();
+        inode_inner.nlink += 1;
+        inode_inner.ctime = inner.mtime;
+        true
+    }
+    /// The data is released when the last link is gone and the file is closed.
+    fn unlink(&self, name: &str) -> bool {
+        if self.kind != TmpInodeKind::Directory {
+            return false;
+        }
+        let mut inner = self.inner.exclusive_access();
+        let index = match inner.find(name) {
+            Some(index) if inner.entries[index].1.kind == TmpInodeKind::File => index,
+            _ => return false,
+        };
+        let (_, inode) = inner.entries.remove(index);
+        inner.touch_modified();
+        let mut inode_inner = inode.inner.exclusive_access();
+        inode_inner.nlink -= 1;
+        inode_inner.ctime = inner.mtime;
+        true
+    }
+    /// An existing `new_name` is replaced, it must be an empty directory if a
+    /// directory is moved, otherwise it must not be a directory.
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn VfsInode>, new_name: &str) -> bool {
+        if [old_name, new_name]
+            .iter()
+            .any(|name| *name == "." || *name == "..")
+        {
+            return false;
+        }
+        let new_dir = match Self::downcast(new_dir) {
+            Some(new_dir) if new_dir.kind == TmpInodeKind::Directory => new_dir,
+            _ => return false,
+        };
+        if self.kind != TmpInodeKind::Directory {
+            return false;
+        }
+        let inode = {
+            let old_inner = self.inner.exclusive_access();
+            match old_inner.find(old_name) {
+                Some(index) => old_inner.entries[index].1.clone(),
+                None => return false,
+            }
+        };
+        let same_dir = self.ino == new_dir.ino;
+        // a directory cannot be moved into itself or its descendants
+        if inode.kind == TmpInodeKind::Directory && new_dir.is_descendant_of(&inode) {
+            return false;
+        }
+        let target = {
+            let new_inner = new_dir.inner.exclusive_access();
+            new_inner
+                .find(new_name)
+                .map(|index| new_inner.entries[index].1.clone())
+        };
+        if let Some(target) = &target {
+            if target.ino == inode.ino {
+                return true;
+            }
+            let valid = match inode.kind {
+                TmpInodeKind::Directory => {
+                    // the old parent is never empty as it holds the moved directory
+                    target.kind == TmpInodeKind::Directory
+                        && target.ino != self.ino
+                        && target.inner.exclusive_access().entries.is_empty()
+                }
+                TmpInodeKind::File => target.kind == TmpInodeKind::File,
+            };
+            if !valid {
+                return false;
+            }
+        }
+        let mut old_inner = self.inner.exclusive_access();
+        let index = old_inner.find(old_name).unwrap();
+        let (_, inode) = old_inner.entries.remove(index);
+        old_inner.touch_modified();
+        let now = old_inner.mtime;
+        let is_dir = inode.kind == TmpInodeKind::Directory;
+        if is_dir && !same_dir {
+            old_inner.nlink -= 1;
+        }
+        drop(old_inner);
+        let mut new_inner = new_dir.inner.exclusive_access();
+        match new_inner.find(new_name) {
+            Some(index) => {
+                let (_, target) = core::mem::replace(
+                    &mut new_inner.entries[index],
+                    (String::from(new_name), inode.clone()),
+                );
+                if is_dir {
+                    new_inner.nlink -= 1;
+                } else {
+                    let mut target_inner = target.inner.exclusive_access();
+                    target_inner.nlink -= 1;
+                    target_inner.ctime = now;
+                }
+            }
+            None => new_inner
+                .entries
+                .push((String::from(new_name), inode.clone())),
+        }
+        new_inner.touch_modified();
+        if is_dir && !same_dir {
+            new_inner.nlink += 1;
+        }
+        drop(new_inner);
+        let mut inode_inner = inode.inner.exclusive_access();
+        inode_inner.parent = new_dir.this.clone();
+        inode_inner.ctime = now;
+        true
+    }
+}
@@ -0,0 +1,147 @@
+use super::PageCache;
+use crate::mm::UserBuffer;
+use crate::syscall::{Stat, StatMode};
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// An opened file, which is what a file descriptor refers to.
+pub trait File: Send + Sync {
+    fn readable(&self) -> bool;
+    fn writable(&self) -> bool;
+    fn read(&self, buf: UserBuffer) -> usize;
+    fn write(&self, buf: UserBuffer) -> usize;
+    fn stat(&self) -> Stat;
+    /// Move the cursor, return the new offset or `None` if the file is not seekable.
+    fn seek(&self, _pos: SeekFrom) -> Option<usize> {
+        None
+    }
+    /// Read at `offset` without moving the cursor, `None` if the file is not seekable.
+    fn read_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// Write at `offset` without moving the cursor, `None` if the file is not seekable.
+    fn write_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// Pass entries from the cursor to `fill` until it returns false, moving the
+    /// cursor past the accepted ones. `None` if this is not a directory.
+    fn read_dir(&self, _fill: &mut dyn FnMut(&Dirent) -> bool) -> Option<()> {
+        None
+    }
+    /// Write the cached data of the file back to its device, `None` if the
+    /// file is not backed by a filesystem.
+    fn sync(&self) -> Option<()> {
+        None
+    }
+    /// Allocate the blocks of `offset..offset + len`, growing the file, false
+    /// if it is not supported.
+    fn allocate(&self, _offset: usize, _len: usize) -> bool {
+        false
+    }
+    /// Free the blocks of `offset..offset + len`, which read as zeros then,
+    /// false if it is not supported.
+    fn punch_hole(&self, _offset: usize, _len: usize) -> bool {
+        false
+    }
+}
+
+pub struct Dirent {
+    pub ino: u64,
+    /// offset of the next entry
+    pub next: usize,
+    pub mode: StatMode,
+    pub name: String,
+}
+
+pub enum SeekFrom {
+    Start(usize),
+    Current(isize),
+    End(isize),
+}
+
+/// Device ids of filesystems, 0 is left to files without one such as pipes.
+static NEXT_DEV: AtomicU64 = AtomicU64::new(1);
+
+/// Allocate the device id of a new filesystem, which its inodes report in
+/// `Stat::dev` so that inode numbers of different filesystems are told apart.
+pub fn alloc_dev() -> u64 {
+    NEXT_DEV.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The superblock of a mounted filesystem.
+pub trait FileSystem: Send + Sync {
+    fn root_inode(&self) -> Arc<dyn VfsInode>;
+    /// Write all cached data back to the device.
+    fn sync(&self) {}
+}
+
+/// An inode of any filesystem, the operations which modify the tree are
+/// refused by default so that read-only filesystems need not implement them.
+///
+/// Directory operations are called on the parent directory, they return
+/// `None` or false if it is not a directory.
+pub trait VfsInode: Send + Sync {
+    /// Used to find out whether two inodes belong to the same kind of filesystem.
+    fn as_any(&self) -> &dyn Any;
+    fn stat(&self) -> Stat;
+    fn is_dir(&self) -> bool {
+        self.stat().mode.contains(StatMode::DIR)
+    }
+    /// Size in bytes, for those which need no more of `stat`, which may count
+    /// the blocks held.
+    fn size(&self) -> usize {
+        self.stat().size as usize
+    }
+    fn find(&self, name: &str) -> Option<Arc<dyn VfsInode>>;
+    /// Return the first entry at or after `slot` with the slot following it.
+    fn read_dir(&self, slot: usize) -> Option<(usize, String, Arc<dyn VfsInode>)>;
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+    /// Return the number of bytes written, which is short if there is no room.
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> usize {
+        0
+    }
+    /// Drop the content.
+    fn clear(&self) {}
+    /// Allocate the blocks of `offset..offset + len` which are holes, growing
+    /// the file to cover them.
+    fn allocate(&self, _offset: usize, _len: usize) -> bool {
+        false
+    }
+    /// Free the blocks within `offset..offset + len`, keeping the size.
+    fn punch_hole(&self, _offset: usize, _len: usize) -> bool {
+        false
+    }
+    /// Write the cached data and metadata of this inode back to the device.
+    fn sync(&self) {}
+    fn create(&self, _name: &str) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+    fn mkdir(&self, _name: &str) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+    fn rmdir(&self, _name: &str) -> bool {
+        false
+    }
+    /// Add `name` referring to `inode`, which must be in the same filesystem.
+    fn link(&self, _name: &str, _inode: &Arc<dyn VfsInode>) -> bool {
+        false
+    }
+    fn unlink(&self, _name: &str) -> bool {
+        false
+    }
+    /// Move `old_name` to `new_name` in `new_dir`, which must be in the same filesystem.
+    fn rename(&self, _old_name: &str, _new_dir: &Arc<dyn VfsInode>, _new_name: &str) -> bool {
+        false
+    }
+    /// Inodes of device files are opened as the device itself instead of an `OSInode`.
+    fn device(&self) -> Option<Arc<dyn File>> {
+        None
+    }
+    /// Reads and writes through files go through the page cache if there is
+    /// one, `read_at` and `write_at` go around it.
+    fn page_cache(&self) -> Option<&PageCache> {
+        None
+    }
+}
@@ -39,6 +39,7 @@ trait FrameAllocator {
 }
 
 pub struct StackFrameAllocator {
+    start: usize,
     current: usize,
     end: usize,
     recycled: Vec<usize>,
@@ -46,14 +47,23 @@ pub struct StackFrameAllocator {
 
 impl StackFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
         self.current = l.0;
         self.end = r.0;
         println!("last {} Physical Frames.", self.end - self.current);
     }
+    /// Number of all frames and of the free ones.
+    pub fn usage(&self) -> (usize, usize) {
+        (
+            self.end - self.start,
+            self.end - self.current + self.recycled.len(),
+        )
+    }
 }
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
         Self {
+            start: 0,
             current: 0,
             end: 0,
             recycled: Vec::new(),
@@ -108,6 +118,11 @@ pub fn frame_dealloc(ppn: PhysPageNum) {
     FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
 }
 
+/// Number of all physical frames and of the free ones.
+pub fn frame_usage() -> (usize, usize) {
+    FRAME_ALLOCATOR.exclusive_access().usage()
+}
+
 #[allow(unused)]
 pub fn frame_allocator_test() {
     let mut v: Vec<FrameTracker> = Vec::new();
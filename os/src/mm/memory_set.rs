@@ -71,6 +71,44 @@ impl MemorySet {
             self.areas.remove(idx);
         }
     }
+    /// Return the first of `pages` free pages at or after `start_vpn`.
+    pub fn find_free_area(&self, start_vpn: VirtPageNum, pages: usize) -> VirtPageNum {
+        let mut start_vpn = start_vpn;
+        while let Some(area) = self
+            .areas
+            .iter()
+            .find(|area| area.overlaps(start_vpn, VirtPageNum(start_vpn.0 + pages)))
+        {
+            start_vpn = area.vpn_range.get_end();
+        }
+        start_vpn
+    }
+    /// Unmap `start_vpn..end_vpn` wherever it is mapped, splitting the areas
+    /// which are only partly in it.
+    pub fn remove_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        let mut idx = 0;
+        while idx < self.areas.len() {
+            let area = &mut self.areas[idx];
+            if !area.overlaps(start_vpn, end_vpn) {
+                idx += 1;
+                continue;
+            }
+            // the pages before the range stay, the rest is looked at next
+            if area.vpn_range.get_start() < start_vpn {
+                let rest = area.split_off(start_vpn);
+                self.areas.insert(idx + 1, rest);
+                idx += 1;
+                continue;
+            }
+            // the pages after the range stay, taking the place of the area
+            if area.vpn_range.get_end() > end_vpn {
+                let rest = area.split_off(end_vpn);
+                self.areas.insert(idx + 1, rest);
+            }
+            let mut area = self.areas.remove(idx);
+            area.unmap(&mut self.page_table);
+        }
+    }
     fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
         map_area.map(&mut self.page_table);
         if let Some(data) = data {
@@ -238,6 +276,16 @@ impl MemorySet {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// Address range and permission of each area, the trampoline excluded.
+    pub fn areas(&self) -> impl Iterator<Item = (VirtAddr, VirtAddr, MapPermission)> + '_ {
+        self.areas.iter().map(|area| {
+            (
+                area.vpn_range.get_start().into(),
+                area.vpn_range.get_end().into(),
+                area.map_perm,
+            )
+        })
+    }
     pub fn recycle_data_pages(&mut self) {
         //*self = Self::new_bare();
         self.areas.clear();
@@ -275,6 +323,20 @@ impl MapArea {
             map_perm: another.map_perm,
         }
     }
+    fn overlaps(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        self.vpn_range.get_start() < end_vpn && start_vpn < self.vpn_range.get_end()
+    }
+    /// Keep the pages before `vpn` and return an area of the rest.
+    pub fn split_off(&mut self, vpn: VirtPageNum) -> Self {
+        let rest = Self {
+            vpn_range: VPNRange::new(vpn, self.vpn_range.get_end()),
+            data_frames: self.data_frames.split_off(&vpn),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+        };
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), vpn);
+        rest
+    }
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         let ppn: PhysPageNum;
         match self.map_type {
@@ -1,7 +1,92 @@
-use crate::fs::{make_pipe, open_file, OpenFlags};
+use crate::fs::{
+    absolute_path, is_dir, link_file, make_dir, make_pipe, open, remove_dir, rename_file,
+    sync_all, unlink_file, OpenFlags, SeekFrom,
+};
 use crate::mm::{translated_byte_buffer, translated_refmut, translated_str, UserBuffer};
 use crate::task::{current_process, current_user_token};
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::*;
+
+/// File status returned by `sys_fstat`, shared with user space.
+#[repr(C)]
+#[derive(Debug)]
+pub struct Stat {
+    /// ID of the device containing the file
+    pub dev: u64,
+    /// inode number
+    pub ino: u64,
+    /// file type
+    pub mode: StatMode,
+    /// permission bits
+    pub perm: u32,
+    /// number of hard links
+    pub nlink: u32,
+    /// always zero, so that no padding is left uninitialized
+    pub pad: u32,
+    /// file size in bytes
+    pub size: u64,
+    /// number of 512-byte blocks allocated, including index blocks
+    pub blocks: u64,
+    /// time of last access in milliseconds since the Unix epoch
+    pub atime: u64,
+    /// time of last modification in milliseconds since the Unix epoch
+    pub mtime: u64,
+    /// time of last status change in milliseconds since the Unix epoch
+    pub ctime: u64,
+}
+
+impl Stat {
+    /// Status of a file which is not backed by an inode.
+    pub fn anonymous(mode: StatMode) -> Self {
+        Self {
+            dev: 0,
+            ino: 0,
+            mode,
+            perm: 0o600,
+            nlink: 1,
+            pad: 0,
+            size: 0,
+            blocks: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+        }
+    }
+}
+
+bitflags! {
+    pub struct StatMode: u32 {
+        const NULL = 0;
+        /// named pipe
+        const FIFO = 0o010000;
+        /// character device
+        const CHR = 0o020000;
+        /// directory
+        const DIR = 0o040000;
+        /// regular file
+        const FILE = 0o100000;
+    }
+}
+
+/// Copy `src` into the user buffer starting at `dst`, which may span pages.
+fn copy_to_user(token: usize, dst: *mut u8, src: &[u8]) {
+    let user_buf = UserBuffer::new(translated_byte_buffer(token, dst, src.len()));
+    for (dst, byte) in user_buf.into_iter().zip(src.iter()) {
+        unsafe {
+            *dst = *byte;
+        }
+    }
+}
+
+/// Load a path from user space and resolve it against the working directory.
+pub fn translated_path(token: usize, path: *const u8) -> String {
+    let path = translated_str(token, path);
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    absolute_path(inner.cwd.as_str(), path.as_str())
+}
 
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
@@ -43,11 +128,151 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     }
 }
 
+const SEEK_SET: usize = 0;
+const SEEK_CUR: usize = 1;
+const SEEK_END: usize = 2;
+
+/// Move the cursor of `fd`, return the new offset or -1 if `fd` is not seekable.
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        drop(inner);
+        let pos = match whence {
+            SEEK_SET if offset >= 0 => SeekFrom::Start(offset as usize),
+            SEEK_CUR => SeekFrom::Current(offset),
+            SEEK_END => SeekFrom::End(offset),
+            _ => return -1,
+        };
+        match file.seek(pos) {
+            Some(offset) => offset as isize,
+            None => -1,
+        }
+    } else {
+        -1
+    }
+}
+
+/// Read from `offset` in `fd` without moving its cursor.
+pub fn sys_pread(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        if !file.readable() {
+            return -1;
+        }
+        drop(inner);
+        match file.read_at(
+            offset,
+            UserBuffer::new(translated_byte_buffer(token, buf, len)),
+        ) {
+            Some(read_size) => read_size as isize,
+            None => -1,
+        }
+    } else {
+        -1
+    }
+}
+
+/// Write to `offset` in `fd` without moving its cursor.
+pub fn sys_pwrite(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        if !file.writable() {
+            return -1;
+        }
+        let file = file.clone();
+        drop(inner);
+        match file.write_at(
+            offset,
+            UserBuffer::new(translated_byte_buffer(token, buf, len)),
+        ) {
+            Some(write_size) => write_size as isize,
+            None => -1,
+        }
+    } else {
+        -1
+    }
+}
+
+/// Size of `d_ino`, `d_off`, `d_reclen` and `d_type` in `struct linux_dirent64`.
+const DIRENT64_HEADER_SIZE: usize = 19;
+
+const DT_FIFO: u8 = 1;
+const DT_CHR: u8 = 2;
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+
+/// Fill `buf` with `struct linux_dirent64` records from the directory `fd`,
+/// return the number of bytes filled, 0 at the end of the directory, or -1
+/// if `fd` is not a directory or `buf` cannot hold the next entry.
+pub fn sys_getdents64(fd: usize, buf: *mut u8, len: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    let file = match &inner.fd_table[fd] {
+        Some(file) if file.readable() => file.clone(),
+        _ => return -1,
+    };
+    drop(inner);
+    let mut dirents: Vec<u8> = Vec::new();
+    let mut too_small = false;
+    let read = file.read_dir(&mut |dirent| {
+        // records are 8-byte aligned and the name is terminated by '\0'
+        let reclen = (DIRENT64_HEADER_SIZE + dirent.name.len() + 1 + 7) & !7;
+        if dirents.len() + reclen > len {
+            too_small = true;
+            return false;
+        }
+        let d_type = if dirent.mode.contains(StatMode::DIR) {
+            DT_DIR
+        } else if dirent.mode.contains(StatMode::FILE) {
+            DT_REG
+        } else if dirent.mode.contains(StatMode::CHR) {
+            DT_CHR
+        } else if dirent.mode.contains(StatMode::FIFO) {
+            DT_FIFO
+        } else {
+            0
+        };
+        let start = dirents.len();
+        dirents.extend_from_slice(&dirent.ino.to_ne_bytes());
+        dirents.extend_from_slice(&(dirent.next as i64).to_ne_bytes());
+        dirents.extend_from_slice(&(reclen as u16).to_ne_bytes());
+        dirents.push(d_type);
+        dirents.extend_from_slice(dirent.name.as_bytes());
+        dirents.resize(start + reclen, 0);
+        true
+    });
+    if read.is_none() || (dirents.is_empty() && too_small) {
+        return -1;
+    }
+    copy_to_user(token, buf, dirents.as_slice());
+    dirents.len() as isize
+}
+
 pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let process = current_process();
     let token = current_user_token();
-    let path = translated_str(token, path);
-    if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
+    let path = translated_path(token, path);
+    if let Some(inode) = open(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
         let mut inner = process.inner_exclusive_access();
         let fd = inner.alloc_fd();
         inner.fd_table[fd] = Some(inode);
@@ -97,3 +322,159 @@ pub fn sys_dup(fd: usize) -> isize {
     inner.fd_table[new_fd] = Some(Arc::clone(inner.fd_table[fd].as_ref().unwrap()));
     new_fd as isize
 }
+
+pub fn sys_mkdir(path: *const u8) -> isize {
+    let token = current_user_token();
+    let path = translated_path(token, path);
+    if make_dir(path.as_str()) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Remove a directory instead of a file in `sys_unlinkat`.
+const AT_REMOVEDIR: u32 = 0x200;
+
+pub fn sys_unlinkat(path: *const u8, flags: u32) -> isize {
+    let token = current_user_token();
+    let path = translated_path(token, path);
+    let removed = if flags & AT_REMOVEDIR != 0 {
+        remove_dir(path.as_str())
+    } else {
+        unlink_file(path.as_str())
+    };
+    if removed {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_linkat(old_path: *const u8, new_path: *const u8) -> isize {
+    let token = current_user_token();
+    let old_path = translated_path(token, old_path);
+    let new_path = translated_path(token, new_path);
+    if link_file(old_path.as_str(), new_path.as_str()) {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_renameat(old_path: *const u8, new_path: *const u8) -> isize {
+    let token = current_user_token();
+    let old_path = translated_path(token, old_path);
+    let new_path = translated_path(token, new_path);
+    if rename_file(old_path.as_str(), new_path.as_str()) {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_chdir(path: *const u8) -> isize {
+    let token = current_user_token();
+    let path = translated_path(token, path);
+    if !is_dir(path.as_str()) {
+        return -1;
+    }
+    current_process().inner_exclusive_access().cwd = path;
+    0
+}
+
+/// Copy the working directory with a terminating `\0` into `buf`,
+/// return the number of bytes copied or -1 if `buf` is too small.
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let mut cwd: Vec<u8> = process.inner_exclusive_access().cwd.as_bytes().to_vec();
+    cwd.push(0);
+    if cwd.len() > len {
+        return -1;
+    }
+    copy_to_user(token, buf, cwd.as_slice());
+    cwd.len() as isize
+}
+
+pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        drop(inner);
+        let stat = file.stat();
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                &stat as *const Stat as *const u8,
+                core::mem::size_of::<Stat>(),
+            )
+        };
+        copy_to_user(token, st as *mut u8, bytes);
+        0
+    } else {
+        -1
+    }
+}
+
+/// Keep the size in `sys_fallocate`, which is only supported along with
+/// `FALLOC_FL_PUNCH_HOLE`.
+const FALLOC_FL_KEEP_SIZE: u32 = 0x1;
+/// Free the range in `sys_fallocate` instead of allocating it.
+const FALLOC_FL_PUNCH_HOLE: u32 = 0x2;
+
+/// Allocate the blocks of `offset..offset + len` in `fd`, growing the file,
+/// or with `FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE` free them instead.
+pub fn sys_fallocate(fd: usize, mode: u32, offset: usize, len: usize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        if !file.writable() {
+            return -1;
+        }
+        let file = file.clone();
+        drop(inner);
+        let done = match mode {
+            0 => file.allocate(offset, len),
+            m if m == FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE => file.punch_hole(offset, len),
+            _ => false,
+        };
+        if done {
+            0
+        } else {
+            -1
+        }
+    } else {
+        -1
+    }
+}
+
+pub fn sys_sync() -> isize {
+    sync_all();
+    0
+}
+
+pub fn sys_fsync(fd: usize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        drop(inner);
+        match file.sync() {
+            Some(()) => 0,
+            None => -1,
+        }
+    } else {
+        -1
+    }
+}
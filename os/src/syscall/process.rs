@@ -1,5 +1,10 @@
+use super::fs::translated_path;
+use crate::config::{MMAP_BASE, PAGE_SIZE, USER_SPACE_END};
 use crate::fs::{open_file, OpenFlags};
-use crate::mm::{translated_ref, translated_refmut, translated_str};
+use crate::mm::{
+    frame_usage, translated_ref, translated_refmut, translated_str, MapPermission, VirtAddr,
+    VirtPageNum,
+};
 use crate::task::{
     current_process, current_task, current_user_token, exit_current_and_run_next, pid2process,
     suspend_current_and_run_next, SignalFlags,
@@ -43,7 +48,7 @@ pub fn sys_fork() -> isize {
 
 pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
     let token = current_user_token();
-    let path = translated_str(token, path);
+    let path = translated_path(token, path);
     let mut args_vec: Vec<String> = Vec::new();
     loop {
         let arg_str_ptr = *translated_ref(token, args);
@@ -115,3 +120,104 @@ pub fn sys_kill(pid: usize, signal: u32) -> isize {
         -1
     }
 }
+
+/// Pages of `sys_mmap` may be read.
+const PROT_READ: u32 = 0x1;
+/// Pages of `sys_mmap` may be written, which needs `PROT_READ` as well.
+const PROT_WRITE: u32 = 0x2;
+/// Pages of `sys_mmap` may be executed.
+const PROT_EXEC: u32 = 0x4;
+/// Changes to the pages of `sys_mmap` are not seen by other processes.
+const MAP_PRIVATE: u32 = 0x02;
+/// Map exactly at the address given to `sys_mmap` instead of taking it as a hint.
+const MAP_FIXED: u32 = 0x10;
+/// Map zeroed pages in `sys_mmap` rather than a file.
+const MAP_ANONYMOUS: u32 = 0x20;
+
+/// Map `len` bytes of zeroed pages with `prot`, return their address or -1.
+///
+/// Only private anonymous mappings are supported, `fd` is ignored and
+/// `offset` must be 0. Mappings are kept within `MMAP_BASE..USER_SPACE_END`:
+/// `addr` outside of it is not taken as a hint, and with `MAP_FIXED` it is
+/// refused, as is `addr` overlapping a mapping already there.
+pub fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: u32,
+    flags: u32,
+    _fd: usize,
+    offset: usize,
+) -> isize {
+    let fixed = match flags {
+        f if f == MAP_PRIVATE | MAP_ANONYMOUS => false,
+        f if f == MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED => true,
+        _ => return -1,
+    };
+    if len == 0 || offset != 0 || addr % PAGE_SIZE != 0 || len > USER_SPACE_END {
+        return -1;
+    }
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return -1;
+    }
+    // the page table can express neither pages without any access nor
+    // writable pages which are not readable
+    if prot == 0 || (prot & PROT_WRITE != 0 && prot & PROT_READ == 0) {
+        return -1;
+    }
+    let mut permission = MapPermission::U;
+    if prot & PROT_READ != 0 {
+        permission |= MapPermission::R;
+    }
+    if prot & PROT_WRITE != 0 {
+        permission |= MapPermission::W;
+    }
+    if prot & PROT_EXEC != 0 {
+        permission |= MapPermission::X;
+    }
+    let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+    // leave frames for the page tables the mapping may need
+    let (_, free) = frame_usage();
+    if pages + pages / 512 + 4 > free {
+        return -1;
+    }
+    let in_range = (MMAP_BASE..USER_SPACE_END).contains(&addr);
+    if fixed && !in_range {
+        return -1;
+    }
+    let hint: VirtPageNum = VirtAddr::from(if in_range { addr } else { MMAP_BASE }).into();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let start_vpn = inner.memory_set.find_free_area(hint, pages);
+    if fixed && start_vpn != hint {
+        return -1;
+    }
+    let end_vpn = VirtPageNum(start_vpn.0 + pages);
+    if end_vpn > VirtAddr::from(USER_SPACE_END).floor() {
+        return -1;
+    }
+    let start_va: VirtAddr = start_vpn.into();
+    let end_va: VirtAddr = end_vpn.into();
+    inner
+        .memory_set
+        .insert_framed_area(start_va, end_va, permission);
+    usize::from(start_va) as isize
+}
+
+/// Unmap the pages of `addr..addr + len`, splitting the mappings which are
+/// only partly in it, return -1 if the range is not within what `sys_mmap`
+/// may map.
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    if len == 0 || addr % PAGE_SIZE != 0 {
+        return -1;
+    }
+    let end = match addr.checked_add(len) {
+        Some(end) if addr >= MMAP_BASE && end <= USER_SPACE_END => end,
+        _ => return -1,
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    inner
+        .memory_set
+        .remove_range(VirtAddr::from(addr).floor(), VirtAddr::from(end).ceil());
+    0
+}
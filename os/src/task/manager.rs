@@ -2,6 +2,7 @@ use super::{ProcessControlBlock, TaskControlBlock};
 use crate::sync::UPIntrFreeCell;
 use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
 
 pub struct TaskManager {
@@ -43,6 +44,11 @@ pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
     map.get(&pid).map(Arc::clone)
 }
 
+/// Pids of all live processes in ascending order.
+pub fn pid_list() -> Vec<usize> {
+    PID2PCB.exclusive_access().keys().copied().collect()
+}
+
 pub fn insert_into_pid2process(pid: usize, process: Arc<ProcessControlBlock>) {
     PID2PCB.exclusive_access().insert(pid, process);
 }
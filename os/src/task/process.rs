@@ -32,6 +32,8 @@ pub struct ProcessControlBlockInner {
     pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
     pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
     pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    /// absolute path of the working directory
+    pub cwd: String,
 }
 
 impl ProcessControlBlockInner {
@@ -99,6 +101,7 @@ impl ProcessControlBlock {
                     mutex_list: Vec::new(),
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
+                    cwd: String::from("/"),
                 })
             },
         });
@@ -218,6 +221,7 @@ impl ProcessControlBlock {
                     mutex_list: Vec::new(),
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
+                    cwd: parent.cwd.clone(),
                 })
             },
         });
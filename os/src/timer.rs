@@ -20,6 +20,17 @@ pub fn get_time_ms() -> usize {
     time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
 }
 
+lazy_static! {
+    /// milliseconds since the Unix epoch at boot, read from the RTC once
+    static ref BOOT_TIME_MS: u64 = crate::board::rtc_time_ms() - get_time_ms() as u64;
+}
+
+/// Milliseconds since the Unix epoch, the time base of file timestamps
+/// shared with the tools writing images on the host.
+pub fn get_real_time_ms() -> u64 {
+    *BOOT_TIME_MS + get_time_ms() as u64
+}
+
 pub fn set_next_trigger() {
     set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
 }
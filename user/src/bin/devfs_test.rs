@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, open, read, write, OpenFlags};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buffer = [0xffu8; 64];
+    let null = open("/dev/null\0", OpenFlags::RDWR);
+    assert!(null > 0);
+    assert_eq!(write(null as usize, b"discarded"), 9);
+    assert_eq!(read(null as usize, &mut buffer), 0);
+    close(null as usize);
+
+    let zero = open("/dev/zero\0", OpenFlags::RDONLY);
+    assert!(zero > 0);
+    assert_eq!(read(zero as usize, &mut buffer), 64);
+    assert!(buffer.iter().all(|byte| *byte == 0));
+    close(zero as usize);
+
+    let random = open("/dev/random\0", OpenFlags::RDONLY);
+    assert!(random > 0);
+    assert_eq!(read(random as usize, &mut buffer), 64);
+    assert!(buffer.iter().any(|byte| *byte != 0));
+    close(random as usize);
+
+    // redirect stdout to /dev/null, then get the console back from /dev/tty
+    close(1);
+    assert_eq!(open("/dev/null\0", OpenFlags::WRONLY), 1);
+    println!("this line goes nowhere");
+    close(1);
+    assert_eq!(open("/dev/tty\0", OpenFlags::WRONLY), 1);
+    println!("devfs_test passed!");
+    0
+}
@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use user_lib::{close, get_time, mkdir, open, rmdir, unlink, OpenFlags};
+
+/// Open `path` with `flags` and close it at once, return whether it was opened.
+fn open_close(path: &str, flags: OpenFlags) -> bool {
+    let fd = open(path, flags);
+    if fd >= 0 {
+        close(fd as usize);
+    }
+    fd >= 0
+}
+
+/// Create `count` files in a new directory, look each one up, look up as many
+/// names which do not exist, then remove them all, timing each pass. Compare
+/// images packed with and without `--linear-dirs`.
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let count: usize = match argv.get(1).map(|count| count.parse()) {
+        None => 1000,
+        Some(Ok(count)) => count,
+        Some(Err(_)) => {
+            println!("usage: dir_bench [count] [dir]");
+            return -1;
+        }
+    };
+    let dir = if argc > 2 { argv[2] } else { "dir_bench" };
+    let mut dir_path = String::from(dir);
+    dir_path.push('\0');
+    if mkdir(dir_path.as_str()) != 0 {
+        println!("dir_bench: cannot create directory {}", dir);
+        return -1;
+    }
+    // each pass is expected to succeed for every file
+    let passes: [(&str, fn(&str, usize) -> bool); 4] = [
+        ("create", |dir, i| {
+            open_close(
+                format!("{}/file{}\0", dir, i).as_str(),
+                OpenFlags::CREATE | OpenFlags::WRONLY,
+            )
+        }),
+        ("lookup", |dir, i| {
+            open_close(format!("{}/file{}\0", dir, i).as_str(), OpenFlags::RDONLY)
+        }),
+        ("miss", |dir, i| {
+            !open_close(format!("{}/none{}\0", dir, i).as_str(), OpenFlags::RDONLY)
+        }),
+        ("unlink", |dir, i| {
+            unlink(format!("{}/file{}\0", dir, i).as_str()) == 0
+        }),
+    ];
+    let mut ret = 0;
+    for (pass, op) in passes.iter() {
+        let start = get_time();
+        let failed = (0..count).filter(|i| !op(dir, *i)).count();
+        let time_ms = get_time() - start;
+        println!("{:>6} {} files in {}ms", pass, count, time_ms);
+        if failed > 0 {
+            println!("dir_bench: {} failed for {} files", pass, failed);
+            ret = -1;
+        }
+    }
+    rmdir(dir_path.as_str());
+    ret
+}
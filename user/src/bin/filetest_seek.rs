@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, lseek, open, pread, pwrite, read, unlink, write, OpenFlags, SEEK_CUR, SEEK_END, SEEK_SET,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let path = "seek_test\0";
+    let fd = open(path, OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"0123456789"), 10);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 10);
+    assert_eq!(lseek(fd, -4, SEEK_END), 6);
+    let mut buffer = [0u8; 16];
+    assert_eq!(read(fd, &mut buffer), 4);
+    assert_eq!(&buffer[..4], b"6789");
+    assert_eq!(lseek(fd, -1, SEEK_SET), -1);
+
+    // positional I/O leaves the cursor alone
+    assert_eq!(lseek(fd, 2, SEEK_SET), 2);
+    assert_eq!(pwrite(fd, b"ab", 8), 2);
+    assert_eq!(pread(fd, &mut buffer[..3], 7), 3);
+    assert_eq!(&buffer[..3], b"7ab");
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 2);
+
+    // writing past the end leaves a zero-filled gap
+    assert_eq!(lseek(fd, 12, SEEK_SET), 12);
+    assert_eq!(write(fd, b"!"), 1);
+    assert_eq!(pread(fd, &mut buffer, 8), 5);
+    assert_eq!(&buffer[..5], b"ab\0\0!");
+    close(fd);
+    assert_eq!(unlink(path), 0);
+    println!("filetest_seek passed!");
+    0
+}
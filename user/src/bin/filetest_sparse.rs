@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, fallocate, fstat, open, pread, pwrite, unlink, OpenFlags, Stat, FALLOC_FL_KEEP_SIZE,
+    FALLOC_FL_PUNCH_HOLE,
+};
+
+fn stat(fd: usize) -> Stat {
+    let mut stat = Stat::new();
+    assert_eq!(fstat(fd, &mut stat), 0);
+    stat
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let path = "sparse_test\0";
+    let fd = open(path, OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    // the gap before a write past the end is a hole, the last block and
+    // the indirect block above it are all there is
+    assert_eq!(pwrite(fd, b"!", 65536), 1);
+    assert_eq!((stat(fd).size, stat(fd).blocks), (65537, 2));
+    let mut buffer = [1u8; 512];
+    assert_eq!(pread(fd, &mut buffer, 1024), 512);
+    assert!(buffer.iter().all(|byte| *byte == 0));
+
+    assert_eq!(pwrite(fd, &[b'a'; 2048], 0), 2048);
+    assert_eq!(stat(fd).blocks, 6);
+    let punch = FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE;
+    assert_eq!(fallocate(fd, punch, 0, 2048), 0);
+    assert_eq!((stat(fd).size, stat(fd).blocks), (65537, 2));
+    assert_eq!(pread(fd, &mut buffer, 0), 512);
+    assert!(buffer.iter().all(|byte| *byte == 0));
+
+    assert_eq!(fallocate(fd, 0, 0, 512), 0);
+    assert_eq!(stat(fd).blocks, 3);
+    assert_eq!(fallocate(fd, 0, 65536, 1024), 0);
+    assert_eq!((stat(fd).size, stat(fd).blocks), (66560, 4));
+    // only punching keeps the size
+    assert_eq!(fallocate(fd, FALLOC_FL_KEEP_SIZE, 0, 512), -1);
+    close(fd);
+    assert_eq!(unlink(path), 0);
+    println!("filetest_sparse passed!");
+    0
+}
@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use user_lib::{close, open, read_dir, OpenFlags, DT_DIR};
+
+/// Print `path` if its last component matches `name`, then descend into it
+/// if it is a directory.
+fn find(path: &str, name: Option<&str>) {
+    let base = path.rsplit('/').next().unwrap();
+    if name.map_or(true, |name| name == base) {
+        println!("{}", path);
+    }
+    let mut c_path = String::from(path);
+    c_path.push('\0');
+    let fd = open(c_path.as_str(), OpenFlags::RDONLY);
+    if fd < 0 {
+        println!("find: cannot open {}", path);
+        return;
+    }
+    let fd = fd as usize;
+    let dirents = read_dir(fd);
+    close(fd);
+    for dirent in dirents.into_iter().flatten() {
+        if dirent.name == "." || dirent.name == ".." {
+            continue;
+        }
+        let mut child = String::from(path.trim_end_matches('/'));
+        child.push('/');
+        child.push_str(dirent.name.as_str());
+        if dirent.d_type == DT_DIR {
+            find(child.as_str(), name);
+        } else if name.map_or(true, |name| name == dirent.name) {
+            println!("{}", child);
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc > 3 {
+        println!("usage: find [path] [name]");
+        return -1;
+    }
+    let path = if argc > 1 { argv[1] } else { "." };
+    let name = if argc > 2 { Some(argv[2]) } else { None };
+    find(path, name);
+    0
+}
@@ -3,16 +3,21 @@
 
 #[macro_use]
 extern crate user_lib;
+extern crate alloc;
 
-use user_lib::{close, get_time, open, write, OpenFlags};
+use alloc::string::String;
+use user_lib::{close, fsync, get_time, open, write, OpenFlags};
 
+/// Write 1MiB to `testf`, or to the path given such as `/tmp/testf`.
 #[no_mangle]
-pub fn main() -> i32 {
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
     let mut buffer = [0u8; 1024]; // 1KiB
     for (i, ch) in buffer.iter_mut().enumerate() {
         *ch = i as u8;
     }
-    let f = open("testf\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    let mut path = String::from(if argc > 1 { argv[1] } else { "testf" });
+    path.push('\0');
+    let f = open(path.as_str(), OpenFlags::CREATE | OpenFlags::WRONLY);
     if f < 0 {
         panic!("Open test file failed!");
     }
@@ -22,6 +27,8 @@ pub fn main() -> i32 {
     for _ in 0..1024 * size_mb {
         write(f, &buffer);
     }
+    // count the time to write the cached blocks back as well
+    fsync(f);
     close(f);
     let time_ms = (get_time() - start) as usize;
     let speed_kbs = (size_mb << 20) / time_ms;
@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{close, open, read_dir, OpenFlags, DT_DIR};
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let target = if argc > 1 { argv[1] } else { "." };
+    let mut path = String::from(target);
+    path.push('\0');
+    let fd = open(path.as_str(), OpenFlags::RDONLY);
+    if fd < 0 {
+        println!("ls: cannot access {}", target);
+        return -1;
+    }
+    let fd = fd as usize;
+    let dirents = read_dir(fd);
+    close(fd);
+    let dirents = match dirents {
+        Some(dirents) => dirents,
+        None => {
+            // not a directory, list the file itself
+            println!("{}", target);
+            return 0;
+        }
+    };
+    let mut names: Vec<String> = dirents
+        .into_iter()
+        .filter(|dirent| dirent.name != "." && dirent.name != "..")
+        .map(|dirent| {
+            let mut name = dirent.name;
+            if dirent.d_type == DT_DIR {
+                name.push('/');
+            }
+            name
+        })
+        .collect();
+    names.sort();
+    for name in names {
+        println!("{}", name);
+    }
+    0
+}
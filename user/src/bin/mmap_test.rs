@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec;
+use user_lib::{
+    fork, mmap, munmap, waitpid, MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, PROT_READ, PROT_WRITE,
+};
+
+const PAGE_SIZE: usize = 4096;
+const FLAGS: u32 = MAP_PRIVATE | MAP_ANONYMOUS;
+
+fn page(addr: usize, i: usize) -> *mut u8 {
+    (addr + i * PAGE_SIZE) as *mut u8
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // pages without access or writable but not readable are refused
+    assert_eq!(mmap(0, PAGE_SIZE, 0, FLAGS), -1);
+    assert_eq!(mmap(0, PAGE_SIZE, PROT_WRITE, FLAGS), -1);
+    assert_eq!(mmap(0, PAGE_SIZE, PROT_READ, MAP_PRIVATE), -1);
+
+    let addr = mmap(0, 4 * PAGE_SIZE, PROT_READ | PROT_WRITE, FLAGS);
+    assert!(addr > 0);
+    let addr = addr as usize;
+    for i in 0..4 {
+        unsafe {
+            assert_eq!(*page(addr, i), 0);
+            *page(addr, i) = i as u8 + 1;
+        }
+    }
+    // a fixed mapping may not overlap another one
+    let prot = PROT_READ | PROT_WRITE;
+    assert_eq!(
+        mmap(addr + PAGE_SIZE, PAGE_SIZE, prot, FLAGS | MAP_FIXED),
+        -1
+    );
+
+    // unmapping the middle pages splits the mapping in two
+    assert_eq!(munmap(addr + PAGE_SIZE, 2 * PAGE_SIZE), 0);
+    unsafe {
+        assert_eq!(*page(addr, 0), 1);
+        assert_eq!(*page(addr, 3), 4);
+    }
+    // the hole can be mapped again, zeroed
+    let fixed = mmap(addr + PAGE_SIZE, PAGE_SIZE, prot, FLAGS | MAP_FIXED);
+    assert_eq!(fixed as usize, addr + PAGE_SIZE);
+    unsafe {
+        assert_eq!(*page(addr, 1), 0);
+    }
+
+    // the child gets a copy of its own
+    let pid = fork();
+    if pid == 0 {
+        unsafe {
+            assert_eq!(*page(addr, 0), 1);
+            *page(addr, 0) = 42;
+        }
+        return 0;
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    unsafe {
+        assert_eq!(*page(addr, 0), 1);
+    }
+    assert_eq!(munmap(addr, 4 * PAGE_SIZE), 0);
+
+    // the heap grows beyond its first chunk by mapping more memory
+    let v = vec![7u8; 64 * 1024];
+    assert!(v.iter().all(|byte| *byte == 7));
+    println!("mmap_test passed!");
+    0
+}
@@ -0,0 +1,26 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use user_lib::rename;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc != 3 {
+        println!("usage: mv <source> <target>");
+        return -1;
+    }
+    let mut old_path = String::from(argv[1]);
+    old_path.push('\0');
+    let mut new_path = String::from(argv[2]);
+    new_path.push('\0');
+    if rename(old_path.as_str(), new_path.as_str()) != 0 {
+        println!("mv: cannot move {} to {}", argv[1], argv[2]);
+        return -1;
+    }
+    0
+}
@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{close, open, read, read_dir, OpenFlags};
+
+/// Whole content of a file under /proc, `None` if it cannot be opened.
+fn read_proc(path: &str) -> Option<String> {
+    let mut path = String::from(path);
+    path.push('\0');
+    let fd = open(path.as_str(), OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let fd = fd as usize;
+    let mut content = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let len = read(fd, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        content.extend_from_slice(&buf[..len as usize]);
+    }
+    close(fd);
+    String::from_utf8(content).ok()
+}
+
+/// Value of the `key:\tvalue` line in `/proc/<pid>/status` or `/proc/meminfo`.
+fn field<'a>(text: &'a str, key: &str) -> &'a str {
+    text.lines()
+        .find_map(|line| {
+            line.strip_prefix(key)
+                .and_then(|rest| rest.strip_prefix(':'))
+                .map(|value| value.trim())
+        })
+        .unwrap_or("?")
+}
+
+/// List the processes along with the uptime and memory usage, read from /proc.
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/proc\0", OpenFlags::RDONLY);
+    if fd < 0 {
+        println!("ps: /proc is not available");
+        return -1;
+    }
+    let dirents = read_dir(fd as usize).unwrap();
+    close(fd as usize);
+    if let (Some(uptime), Some(meminfo)) = (read_proc("/proc/uptime"), read_proc("/proc/meminfo")) {
+        println!(
+            "up {}s, memory {} free of {}",
+            uptime.trim(),
+            field(&meminfo, "MemFree"),
+            field(&meminfo, "MemTotal")
+        );
+    }
+    println!(
+        "{:>5} {:>5} {:>9} {:>4} {:>8}  CWD",
+        "PID", "PPID", "STATE", "THR", "VSZ"
+    );
+    for dirent in dirents {
+        if dirent.name.parse::<usize>().is_err() {
+            continue;
+        }
+        // the process may have exited since the directory was read
+        let status = match read_proc(format!("/proc/{}/status", dirent.name).as_str()) {
+            Some(status) if !status.is_empty() => status,
+            _ => continue,
+        };
+        println!(
+            "{:>5} {:>5} {:>9} {:>4} {:>8}  {}",
+            dirent.name,
+            field(&status, "PPid"),
+            field(&status, "State"),
+            field(&status, "Threads"),
+            field(&status, "VmSize"),
+            field(&status, "Cwd")
+        );
+    }
+    0
+}
@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use user_lib::{rmdir, unlink};
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let remove_dir = argc > 1 && argv[1] == "-d";
+    let paths = if remove_dir { &argv[2..] } else { &argv[1..] };
+    if paths.is_empty() {
+        println!("usage: rm [-d] <path>...");
+        return -1;
+    }
+    let mut ret = 0;
+    for path in paths.iter() {
+        let mut path_z = String::from(*path);
+        path_z.push('\0');
+        let removed = if remove_dir {
+            rmdir(path_z.as_str())
+        } else {
+            unlink(path_z.as_str())
+        };
+        if removed != 0 {
+            println!("rm: cannot remove {}", path);
+            ret = -1;
+        }
+    }
+    ret
+}
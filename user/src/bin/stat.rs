@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::string::String;
+use user_lib::{close, fstat, open, OpenFlags, Stat, StatMode};
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc != 2 {
+        println!("usage: stat <path>");
+        return -1;
+    }
+    let mut path = String::from(argv[1]);
+    path.push('\0');
+    let fd = open(path.as_str(), OpenFlags::RDONLY);
+    if fd < 0 {
+        println!("stat: cannot open {}", argv[1]);
+        return -1;
+    }
+    let fd = fd as usize;
+    let mut st = Stat::new();
+    let ret = fstat(fd, &mut st);
+    close(fd);
+    if ret != 0 {
+        println!("stat: cannot stat {}", argv[1]);
+        return -1;
+    }
+    let kind = if st.mode.contains(StatMode::DIR) {
+        "directory"
+    } else if st.mode.contains(StatMode::FILE) {
+        "regular file"
+    } else {
+        "other"
+    };
+    println!("  File: {}", argv[1]);
+    println!("  Size: {}\tBlocks: {}\t{}", st.size, st.blocks, kind);
+    println!(" Inode: {}\tLinks: {}\tAccess: {:o}", st.ino, st.nlink, st.perm);
+    println!("Access: {}", st.atime);
+    println!("Modify: {}", st.mtime);
+    println!("Change: {}", st.ctime);
+    0
+}
@@ -16,7 +16,7 @@ const LINE_START: &str = ">> ";
 use alloc::string::String;
 use alloc::vec::Vec;
 use user_lib::console::getchar;
-use user_lib::{close, dup, exec, fork, open, pipe, waitpid, OpenFlags};
+use user_lib::{chdir, close, dup, exec, fork, open, pipe, waitpid, OpenFlags};
 
 #[derive(Debug)]
 struct ProcessArguments {
@@ -74,6 +74,21 @@ impl ProcessArguments {
     }
 }
 
+/// Run `cd` in the shell itself since it changes the state of the shell process,
+/// return false if the line is not a `cd` command.
+fn builtin_cd(line: &str) -> bool {
+    let args: Vec<_> = line.split(' ').filter(|arg| !arg.is_empty()).collect();
+    if args.first() != Some(&"cd") {
+        return false;
+    }
+    let mut path = String::from(*args.get(1).unwrap_or(&"/"));
+    path.push('\0');
+    if chdir(path.as_str()) != 0 {
+        println!("cd: cannot change directory to {}", args[1]);
+    }
+    true
+}
+
 #[no_mangle]
 pub fn main() -> i32 {
     println!("Rust user shell");
@@ -84,7 +99,9 @@ pub fn main() -> i32 {
         match c {
             LF | CR => {
                 println!("");
-                if !line.is_empty() {
+                if builtin_cd(line.as_str()) {
+                    line.clear();
+                } else if !line.is_empty() {
                     let splited: Vec<_> = line.as_str().split('|').collect();
                     let process_arguments_list: Vec<_> = splited
                         .iter()
@@ -173,11 +190,15 @@ pub fn main() -> i32 {
                                     close(pipe_fd[1]);
                                 }
                                 // execute new application
-                                if exec(args_copy[0].as_str(), args_addr.as_slice()) == -1 {
-                                    println!("Error when executing!");
-                                    return -4;
+                                exec(args_copy[0].as_str(), args_addr.as_slice());
+                                // applications live in the root directory
+                                if !args_copy[0].contains('/') {
+                                    let mut path = String::from("/");
+                                    path.push_str(args_copy[0].as_str());
+                                    exec(path.as_str(), args_addr.as_slice());
                                 }
-                                unreachable!();
+                                println!("Error when executing!");
+                                return -4;
                             } else {
                                 children.push(pid);
                             }
@@ -5,13 +5,17 @@
 extern crate user_lib;
 
 static TESTS: &[&str] = &[
+    "devfs_test\0",
     "exit\0",
     "fantastic_text\0",
+    "filetest_seek\0",
+    "filetest_sparse\0",
     "forktest\0",
     "forktest2\0",
     "forktest_simple\0",
     "hello_world\0",
     "matrix\0",
+    "mmap_test\0",
     "sleep\0",
     "sleep_simple\0",
     "stack_overflow\0",
@@ -12,16 +12,43 @@ extern crate alloc;
 #[macro_use]
 extern crate bitflags;
 
+use alloc::string::String;
 use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::convert::TryInto;
+use core::ptr::{null_mut, NonNull};
 use syscall::*;
 
+/// The heap grows by mapping at least this much memory at a time.
 const USER_HEAP_SIZE: usize = 32768;
 
-static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
+/// A heap which maps more memory whenever it runs out.
+struct UserHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for UserHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        if let Ok(ptr) = heap.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        // twice the size of a block always holds one which is aligned
+        let block = layout.size().max(layout.align()).next_power_of_two();
+        let len = (2 * block).max(USER_HEAP_SIZE);
+        let start = mmap(0, len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS);
+        if start < 0 {
+            return null_mut();
+        }
+        heap.add_to_heap(start as usize, start as usize + len);
+        heap.alloc(layout).map_or(null_mut(), |ptr| ptr.as_ptr())
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout);
+    }
+}
 
 #[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
+static HEAP: UserHeap = UserHeap(LockedHeap::empty());
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -31,10 +58,6 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
 #[no_mangle]
 #[link_section = ".text.entry"]
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
-    unsafe {
-        HEAP.lock()
-            .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
-    }
     let mut v: Vec<&'static str> = Vec::new();
     for i in 0..argc {
         let str_start =
@@ -68,15 +91,112 @@ bitflags! {
     }
 }
 
+#[repr(C)]
+#[derive(Debug)]
+pub struct Stat {
+    /// ID of the device containing the file
+    pub dev: u64,
+    /// inode number
+    pub ino: u64,
+    /// file type
+    pub mode: StatMode,
+    /// permission bits
+    pub perm: u32,
+    /// number of hard links
+    pub nlink: u32,
+    /// always zero, so that no padding is left uninitialized
+    pub pad: u32,
+    /// file size in bytes
+    pub size: u64,
+    /// number of 512-byte blocks allocated, including index blocks
+    pub blocks: u64,
+    /// time of last access in milliseconds since the Unix epoch
+    pub atime: u64,
+    /// time of last modification in milliseconds since the Unix epoch
+    pub mtime: u64,
+    /// time of last status change in milliseconds since the Unix epoch
+    pub ctime: u64,
+}
+
+impl Stat {
+    pub fn new() -> Self {
+        Self {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::NULL,
+            perm: 0,
+            nlink: 0,
+            pad: 0,
+            size: 0,
+            blocks: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+        }
+    }
+}
+
+impl Default for Stat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+bitflags! {
+    pub struct StatMode: u32 {
+        const NULL = 0;
+        /// named pipe
+        const FIFO = 0o010000;
+        /// character device
+        const CHR = 0o020000;
+        /// directory
+        const DIR = 0o040000;
+        /// regular file
+        const FILE = 0o100000;
+    }
+}
+
+pub fn getcwd(buf: &mut [u8]) -> isize {
+    sys_getcwd(buf)
+}
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
+pub fn mkdir(path: &str) -> isize {
+    sys_mkdir(path)
+}
+const AT_REMOVEDIR: u32 = 0x200;
+
+pub fn rmdir(path: &str) -> isize {
+    sys_unlinkat(path, AT_REMOVEDIR)
+}
+pub fn link(old_path: &str, new_path: &str) -> isize {
+    sys_linkat(old_path, new_path)
+}
+pub fn unlink(path: &str) -> isize {
+    sys_unlinkat(path, 0)
+}
+pub fn rename(old_path: &str, new_path: &str) -> isize {
+    sys_renameat(old_path, new_path)
+}
+pub fn chdir(path: &str) -> isize {
+    sys_chdir(path)
+}
 pub fn open(path: &str, flags: OpenFlags) -> isize {
     sys_open(path, flags.bits)
 }
 pub fn close(fd: usize) -> isize {
     sys_close(fd)
 }
+pub fn fstat(fd: usize, st: &mut Stat) -> isize {
+    sys_fstat(fd, st)
+}
+pub fn sync() -> isize {
+    sys_sync()
+}
+pub fn fsync(fd: usize) -> isize {
+    sys_fsync(fd)
+}
 pub fn pipe(pipe_fd: &mut [usize]) -> isize {
     sys_pipe(pipe_fd)
 }
@@ -86,6 +206,92 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
 pub fn write(fd: usize, buf: &[u8]) -> isize {
     sys_write(fd, buf)
 }
+pub fn getdents(fd: usize, buf: &mut [u8]) -> isize {
+    sys_getdents64(fd, buf)
+}
+
+pub const DT_FIFO: u8 = 1;
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+
+pub struct Dirent {
+    pub ino: u64,
+    pub d_type: u8,
+    pub name: String,
+}
+
+/// Read all entries of the directory opened as `fd`, including "." and "..",
+/// return `None` if `fd` is not a directory.
+pub fn read_dir(fd: usize) -> Option<Vec<Dirent>> {
+    let mut buf = [0u8; 512];
+    let mut dirents = Vec::new();
+    loop {
+        let len = getdents(fd, &mut buf);
+        if len < 0 {
+            return None;
+        }
+        if len == 0 {
+            return Some(dirents);
+        }
+        let mut pos = 0;
+        while pos < len as usize {
+            let record = &buf[pos..];
+            let ino = u64::from_ne_bytes(record[0..8].try_into().unwrap());
+            let reclen = u16::from_ne_bytes(record[16..18].try_into().unwrap()) as usize;
+            let d_type = record[18];
+            let name_len = record[19..reclen].iter().position(|b| *b == 0).unwrap();
+            let name = core::str::from_utf8(&record[19..19 + name_len]).unwrap();
+            dirents.push(Dirent {
+                ino,
+                d_type,
+                name: String::from(name),
+            });
+            pos += reclen;
+        }
+    }
+}
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    sys_lseek(fd, offset, whence)
+}
+pub fn pread(fd: usize, buf: &mut [u8], offset: usize) -> isize {
+    sys_pread(fd, buf, offset)
+}
+pub fn pwrite(fd: usize, buf: &[u8], offset: usize) -> isize {
+    sys_pwrite(fd, buf, offset)
+}
+
+pub const FALLOC_FL_KEEP_SIZE: u32 = 0x1;
+pub const FALLOC_FL_PUNCH_HOLE: u32 = 0x2;
+
+/// Allocate `offset..offset + len` in `fd` with `mode` 0, or punch a hole
+/// there with `FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE`.
+pub fn fallocate(fd: usize, mode: u32, offset: usize, len: usize) -> isize {
+    sys_fallocate(fd, mode, offset, len)
+}
+
+pub const PROT_READ: u32 = 0x1;
+pub const PROT_WRITE: u32 = 0x2;
+pub const PROT_EXEC: u32 = 0x4;
+pub const MAP_PRIVATE: u32 = 0x02;
+pub const MAP_FIXED: u32 = 0x10;
+pub const MAP_ANONYMOUS: u32 = 0x20;
+
+/// Map `len` bytes of zeroed memory with `prot`, exactly at `addr` with
+/// `MAP_FIXED`, return its address or -1. `flags` must include
+/// `MAP_PRIVATE | MAP_ANONYMOUS`, which is all that is supported.
+pub fn mmap(addr: usize, len: usize, prot: u32, flags: u32) -> isize {
+    sys_mmap(addr, len, prot, flags)
+}
+/// Unmap `addr..addr + len`, parts of mappings which are outside of it stay.
+pub fn munmap(addr: usize, len: usize) -> isize {
+    sys_munmap(addr, len)
+}
 pub fn exit(exit_code: i32) -> ! {
     sys_exit(exit_code);
 }